@@ -1,27 +1,57 @@
 use std::fmt::Debug;
 use std::sync::Arc;
-use futures::stream::{self, StreamExt};
+
 use async_trait::async_trait;
-use futures::Sink;
+use futures::stream;
+use futures::{Sink, SinkExt, StreamExt};
 use tokio::net::TcpListener;
-use tokio_postgres::{Client, NoTls, Row, SimpleQueryMessage};
-use tokio_postgres::SimpleQueryRow;
-use pgwire::api::results::{FieldFormat};
+use tokio_postgres::{NoTls, SimpleQueryMessage, SimpleQueryRow};
 
-use pgwire::api::auth::{AuthSource, DefaultServerParameterProvider, LoginInfo};
 use pgwire::api::auth::noop::NoopStartupHandler;
-use pgwire::api::query::{ExtendedQueryHandler, PlaceholderExtendedQueryHandler, SimpleQueryHandler};
-use pgwire::api::results::{DataRowEncoder, DescribePortalResponse, DescribeStatementResponse, FieldInfo, QueryResponse, Response, Tag};
-use pgwire::api::{ClientInfo, ClientPortalStore, StatelessMakeHandler, Type};
-use pgwire::api::portal::Portal;
+use pgwire::api::cancel::{self, CancelHandler, CancelToken};
+use pgwire::api::copy::{CopyDataStream, CopyHandler, CopyResponse};
+use pgwire::api::portal::{Portal, RawParameter};
+use pgwire::api::query::{ExtendedQueryHandler, PlaceholderQueryParser, QueryParser, SimpleQueryHandler};
+use pgwire::api::results::{
+    schema_from_columns, DataRowEncoder, DescribePortalResponse, DescribeStatementResponse, FieldFormat, FieldInfo,
+    PassThroughStream, QueryResponse, Response, Tag,
+};
 use pgwire::api::stmt::StoredStatement;
 use pgwire::api::store::PortalStore;
-use pgwire::error::{PgWireError, PgWireResult};
-use pgwire::messages::PgWireBackendMessage;
+use pgwire::api::{ClientInfo, ClientPortalStore, PgWireHandlerFactory};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult, SqlState};
+use pgwire::messages::{self, PgWireBackendMessage};
 use pgwire::tokio::process_socket;
 
+/// Relays every handler call straight to an upstream PostgreSQL server.
 pub struct ProxyProcessor {
-    upstream_client: Client,
+    upstream_client: tokio_postgres::Client,
+    /// The upstream `COPY FROM STDIN` sink for the in-progress copy, if
+    /// any. Opened by `copy_in_response` and fed one frame at a time by
+    /// `on_copy_data`. Boxed and pinned because
+    /// `CopyInSink` is itself `!Unpin`.
+    copy_in_sink: tokio::sync::Mutex<Option<std::pin::Pin<Box<tokio_postgres::CopyInSink<bytes::Bytes>>>>>,
+    /// Token for cancelling the query currently running on
+    /// `upstream_client`, used to relay an incoming `CancelRequest` to the
+    /// real upstream server.
+    upstream_cancel_token: tokio_postgres::CancelToken,
+}
+
+impl ProxyProcessor {
+    pub fn new(upstream_client: tokio_postgres::Client) -> ProxyProcessor {
+        let upstream_cancel_token = upstream_client.cancel_token();
+        ProxyProcessor {
+            upstream_client,
+            copy_in_sink: tokio::sync::Mutex::new(None),
+            upstream_cancel_token,
+        }
+    }
+}
+
+/// Translate an upstream `tokio_postgres` failure into a `PgWireError`,
+/// preserving its SQLSTATE and message when the server reported one.
+fn translate_upstream_error(e: tokio_postgres::Error) -> PgWireError {
+    PgWireError::from(e)
 }
 
 #[async_trait]
@@ -30,151 +60,372 @@ impl SimpleQueryHandler for ProxyProcessor {
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        self.upstream_client
+        let resp_msgs = self
+            .upstream_client
             .simple_query(query)
             .await
-            .map_err(|e| PgWireError::ApiError(Box::new(e)))
-            .map(|resp_msgs| {
-                let mut downstream_response = Vec::new();
-                let mut row_buf = Vec::new();
-                for resp in resp_msgs {
-                    match resp {
-                        SimpleQueryMessage::CommandComplete(count) => {
-                            if row_buf.is_empty() {
-                                downstream_response.push(Response::Execution(
-                                    Tag::new_for_execution("", Some(count as usize)),
-                                ));
-                            } else {
-                                // Convert buffered rows to QueryResponse
-                                let query_response = encode_query_response(&row_buf);
-                                downstream_response.push(Response::Query(query_response));
-                            }
-                        }
-                        SimpleQueryMessage::Row(row) => {
-                            // Buffer the row for later processing
-                            // row_buf.push(&row);
-                        }
-                        _ => {}
-                    }
-                }
-                downstream_response
-            })
+            .map_err(translate_upstream_error)?;
+
+        Ok(vec![Response::PassThrough(pass_through_simple_query(resp_msgs))])
     }
 }
 
-fn encode_query_response(rows: &Vec<Row>) -> QueryResponse {
-    // Define the schema according to your data structure
-    let schema = vec![
-        ("column1", FieldFormat::Text),
-        ("column2", FieldFormat::Text),
-        // Add more columns if needed based on your actual schema
-    ];
-
-    let mut encoded_rows = Vec::new(); // This will store encoded DataRow objects
-
-    // Iterate over each row to encode it
-    for row in rows {
-        let mut encoder = DataRowEncoder::new(schema.clone());
-        for (index, (_col_name, _format)) in schema.iter().enumerate() {
-            // Access the row data using the index, and encode each field.
-            // Assuming fields are of type String, adjust the parsing based on actual types.
-            if let Some(value) = row.get::<_, String>(index) {
-                encoder.encode_field(&value).unwrap();
-            } else {
-                // Handle NULL values
-                encoder.encode_field(&None::<String>).unwrap();
+/// Relays a simple-query result session as already-framed backend messages
+/// instead of going through `DataRowEncoder`, so a multi-statement query's
+/// `RowDescription`/`DataRow`/`CommandComplete` groups reach the client in
+/// the order the upstream server produced them. The frames are rebuilt from
+/// the decoded `SimpleQueryMessage`s, not copied: every column is described
+/// as text, a statement that returned no rows gets no `RowDescription`, and
+/// each command tag is `SELECT n` after rows or the bare row count otherwise.
+fn pass_through_simple_query(resp_msgs: Vec<SimpleQueryMessage>) -> PassThroughStream<'static> {
+    let mut framed = Vec::new();
+    let mut row_count = 0usize;
+    let mut described = false;
+
+    for resp in resp_msgs {
+        match resp {
+            SimpleQueryMessage::Row(row) => {
+                if !described {
+                    framed.push(Ok(PgWireBackendMessage::RowDescription(row_description(&row))));
+                    described = true;
+                }
+                framed.push(Ok(PgWireBackendMessage::DataRow(data_row(&row))));
+                row_count += 1;
+            }
+            SimpleQueryMessage::CommandComplete(count) => {
+                let text = if described {
+                    command_tag("SELECT", row_count)
+                } else {
+                    command_tag("", count as usize)
+                };
+                framed.push(Ok(PgWireBackendMessage::CommandComplete(messages::CommandComplete(text))));
+                row_count = 0;
+                described = false;
             }
+            _ => {}
         }
-        // Finish encoding the row and add it to the encoded rows vector
-        encoded_rows.push(Ok(encoder.finish()));
     }
 
-    // Convert the vector into a stream
-    let rows_stream = stream::iter(encoded_rows);
+    Box::pin(stream::iter(framed))
+}
 
-    // Construct the QueryResponse from the schema and the stream of rows
-    QueryResponse::new(schema, rows_stream)
+/// `SimpleQueryRow` does not expose column types, so every field is
+/// described as text.
+fn row_description(row: &SimpleQueryRow) -> messages::RowDescription {
+    messages::RowDescription {
+        fields: (0..row.columns().len())
+            .map(|idx| messages::RowDescriptionField {
+                name: row.columns()[idx].name().to_owned(),
+                table_id: 0,
+                column_id: 0,
+                type_oid: pgwire::api::Type::TEXT.oid() as i32,
+                format: i16::from(FieldFormat::Text),
+            })
+            .collect(),
+    }
+}
+
+fn data_row(row: &SimpleQueryRow) -> messages::DataRow {
+    messages::DataRow {
+        fields: (0..row.columns().len())
+            .map(|idx| row.get(idx).map(|s| s.as_bytes().to_vec()))
+            .collect(),
+    }
+}
+
+fn command_tag(name: &str, rows: usize) -> String {
+    if name.is_empty() {
+        rows.to_string()
+    } else {
+        format!("{} {}", name, rows)
+    }
 }
 
 #[async_trait]
 impl ExtendedQueryHandler for ProxyProcessor {
     type Statement = String;
-    type QueryParser = ();
+    type QueryParser = PlaceholderQueryParser;
 
     fn query_parser(&self) -> Arc<Self::QueryParser> {
-        todo!()
+        Arc::new(PlaceholderQueryParser)
     }
 
-    async fn do_describe_statement<C>(&self, client: &mut C, target: &StoredStatement<Self::Statement>) -> PgWireResult<DescribeStatementResponse>
+    async fn do_describe_statement<C>(
+        &self,
+        _client: &mut C,
+        target: &StoredStatement<Self::Statement>,
+    ) -> PgWireResult<DescribeStatementResponse>
     where
         C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
-        C::PortalStore: PortalStore<Statement=Self::Statement>,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
         C::Error: Debug,
-        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
     {
-        todo!()
+        let stmt = self
+            .upstream_client
+            .prepare(&target.statement)
+            .await
+            .map_err(translate_upstream_error)?;
+
+        let fields = stmt
+            .columns()
+            .iter()
+            .map(|col| FieldInfo::new(col.name().to_owned(), 0, 0, col.type_().clone(), FieldFormat::Text))
+            .collect();
+
+        Ok(DescribeStatementResponse::new(stmt.params().to_vec(), fields))
     }
 
-    async fn do_describe_portal<C>(&self, client: &mut C, target: &Portal<Self::Statement>) -> PgWireResult<DescribePortalResponse>
+    async fn do_describe_portal<C>(
+        &self,
+        _client: &mut C,
+        target: &Portal<Self::Statement>,
+    ) -> PgWireResult<DescribePortalResponse>
     where
         C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
-        C::PortalStore: PortalStore<Statement=Self::Statement>,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
         C::Error: Debug,
-        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
     {
-        todo!()
+        let stmt = self
+            .upstream_client
+            .prepare(&target.statement.statement)
+            .await
+            .map_err(translate_upstream_error)?;
+
+        let fields: Vec<FieldInfo> = stmt
+            .columns()
+            .iter()
+            .map(|col| FieldInfo::new(col.name().to_owned(), 0, 0, col.type_().clone(), FieldFormat::Text))
+            .collect();
+
+        DescribePortalResponse::new(fields).with_result_formats(&target.result_column_format_codes)
     }
 
     async fn do_query<'a, C>(
         &self,
         _client: &mut C,
-        portal: &'a pgwire::api::portal::Portal<Self::Statement>,
+        portal: &'a Portal<Self::Statement>,
         _max_rows: usize,
     ) -> PgWireResult<Response<'a>>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        // Implement the logic for handling extended queries
         let query = &portal.statement.statement;
-        let params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![]; // Extract params from the portal
-        let rows = self.upstream_client.query(query, &params).await.map_err(|e| PgWireError::ApiError(Box::new(e)))?;
+        let params: Vec<RawParameter> = portal
+            .parameters
+            .iter()
+            .map(|p| RawParameter {
+                format: p.format,
+                value: p.value.clone(),
+            })
+            .collect();
+        let params_ref: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = params
+            .iter()
+            .map(|p| p as &(dyn tokio_postgres::types::ToSql + Sync))
+            .collect();
+
+        let stmt = self
+            .upstream_client
+            .prepare(query)
+            .await
+            .map_err(translate_upstream_error)?;
+        let rows = self
+            .upstream_client
+            .query(&stmt, &params_ref)
+            .await
+            .map_err(translate_upstream_error)?;
+
+        let result_formats = portal.result_column_formats(stmt.columns().len())?;
+
+        let mut schema = schema_from_columns(stmt.columns(), FieldFormat::Text);
+        for (field, format) in schema.iter_mut().zip(result_formats.iter().copied()) {
+            field.format = format;
+        }
+        let schema = Arc::new(schema);
+        let row_schema = schema.clone();
+
+        // tokio_postgres always requests binary from the upstream server.
+        let data_rows = stream::iter(rows.into_iter().map(move |row| {
+            let mut encoder = DataRowEncoder::new(row_schema.clone());
+            for idx in 0..row_schema.len() {
+                encoder.encode_field_from_row(&row, idx, FieldFormat::Binary)?;
+            }
+            encoder.finish()
+        }));
 
-        let query_response = encode_query_response(&rows);
-        Ok(Response::Query(query_response))
+        Ok(Response::Query(QueryResponse::new(schema, data_rows)))
     }
 }
 
-#[tokio::main]
-pub async fn main() {
-    let (client, connection) = tokio_postgres::connect("host=127.0.0.1 user=postgres", NoTls)
-        .await
-        .expect("Cannot client upstream connection");
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Upstream connection error: {}", e);
-        }
-    });
+#[async_trait]
+impl CopyHandler for ProxyProcessor {
+    async fn copy_in_response<C>(&self, _client: &C, query: &str) -> PgWireResult<CopyResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let sink = self
+            .upstream_client
+            .copy_in(query)
+            .await
+            .map_err(translate_upstream_error)?;
+        *self.copy_in_sink.lock().await = Some(Box::pin(sink));
+        Ok(CopyResponse::new(FieldFormat::Text, Vec::new()))
+    }
 
-    let processor = Arc::new(StatelessMakeHandler::new(Arc::new(ProxyProcessor {
-        upstream_client: client,
-    })));
+    async fn on_copy_data<C>(&self, _client: &C, data: Vec<u8>) -> PgWireResult<()>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let mut guard = self.copy_in_sink.lock().await;
+        let sink = guard.as_mut().ok_or_else(|| {
+            PgWireError::InvalidProtocolMessage(
+                "CopyData received with no COPY FROM STDIN in progress".to_owned(),
+            )
+        })?;
+        sink.send(bytes::Bytes::from(data))
+            .await
+            .map_err(translate_upstream_error)
+    }
+
+    async fn on_copy_done<C>(&self, _client: &C) -> PgWireResult<Tag>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let mut sink = self.copy_in_sink.lock().await.take().ok_or_else(|| {
+            PgWireError::InvalidProtocolMessage(
+                "CopyDone received with no COPY FROM STDIN in progress".to_owned(),
+            )
+        })?;
+        let rows = sink.as_mut().finish().await.map_err(translate_upstream_error)?;
+        Ok(Tag::new_for_execution("COPY", Some(rows as usize)))
+    }
+
+    async fn on_copy_fail<C>(&self, _client: &C, message: String) -> ErrorInfo
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        // Dropping the sink without finishing it sends `CopyFail` to the
+        // upstream server, aborting the copy there too.
+        self.copy_in_sink.lock().await.take();
+        ErrorInfo::new("ERROR".to_owned(), SqlState::from("57014"), message)
+    }
+
+    async fn copy_out<'a, C>(&self, _client: &C, query: &'a str) -> PgWireResult<(CopyResponse, CopyDataStream<'a>)>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let stream = self
+            .upstream_client
+            .copy_out(query)
+            .await
+            .map_err(translate_upstream_error)?;
+
+        let data_stream = stream.map(|item| {
+            item.map(|bytes| bytes.to_vec())
+                .map_err(translate_upstream_error)
+        });
+
+        Ok((CopyResponse::new(FieldFormat::Text, Vec::new()), Box::pin(data_stream)))
+    }
+}
+
+#[async_trait]
+impl CancelHandler for ProxyProcessor {
+    async fn on_cancel<C>(&self, _client: &C, _token: CancelToken) -> PgWireResult<()>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        // Relaying a real cancel request to the upstream server naturally
+        // surfaces `57014 query_canceled` through `translate_upstream_error`
+        // once the in-flight `do_query` call's `Client::query` future
+        // observes the upstream abort.
+        self.upstream_cancel_token
+            .cancel_query(tokio_postgres::NoTls)
+            .await
+            .map_err(translate_upstream_error)
+    }
+}
+
+/// Hands each accepted connection its own `ProxyProcessor` -- and so its
+/// own upstream session, `copy_in_sink` and `upstream_cancel_token` --
+/// while still sharing the one cancellation registry every connection
+/// must register with to make `CancelRequest` resolvable. One
+/// `ProxyHandlerFactory` is built per accepted socket; without this, two
+/// concurrent clients running `COPY FROM STDIN` would stomp on each
+/// other's `copy_in_sink`, and a `CancelRequest` could cancel whatever
+/// happened to be running on someone else's connection.
+struct ProxyHandlerFactory {
+    processor: Arc<ProxyProcessor>,
+    cancellation_registry: Arc<cancel::MemCancellationRegistry>,
+}
+
+impl PgWireHandlerFactory for ProxyHandlerFactory {
+    type StartupHandler = NoopStartupHandler;
+    type SimpleQueryHandler = ProxyProcessor;
+    type ExtendedQueryHandler = ProxyProcessor;
+    type CopyHandler = ProxyProcessor;
+    type CancelHandler = ProxyProcessor;
+
+    fn simple_query_handler(&self) -> Arc<Self::SimpleQueryHandler> {
+        self.processor.clone()
+    }
+
+    fn extended_query_handler(&self) -> Arc<Self::ExtendedQueryHandler> {
+        self.processor.clone()
+    }
+
+    fn startup_handler(&self) -> Arc<Self::StartupHandler> {
+        Arc::new(NoopStartupHandler)
+    }
 
-    // We have not implemented extended query in this server, use placeholder instead
-    let placeholder = Arc::new(StatelessMakeHandler::new(Arc::new(
-        PlaceholderExtendedQueryHandler,
-    )));
-    let authenticator = Arc::new(StatelessMakeHandler::new(Arc::new(NoopStartupHandler)));
+    fn copy_handler(&self) -> Arc<Self::CopyHandler> {
+        self.processor.clone()
+    }
+
+    fn cancel_handler(&self) -> Arc<Self::CancelHandler> {
+        self.processor.clone()
+    }
+
+    fn cancellation_registry(&self) -> Arc<dyn cancel::CancellationRegistry> {
+        self.cancellation_registry.clone()
+    }
+}
+
+const UPSTREAM_DSN: &str = "host=127.0.0.1 user=postgres";
+
+#[tokio::main]
+pub async fn main() {
+    // Shared across every connection so a `CancelRequest` on one socket can
+    // find the `BackendKeyData` issued on another; everything else about a
+    // connection -- its upstream session, `ProxyProcessor` -- is per-accept.
+    let cancellation_registry = Arc::new(cancel::MemCancellationRegistry::new());
 
     let server_addr = "127.0.0.1:5431";
     let listener = TcpListener::bind(server_addr).await.unwrap();
     println!("Listening to {}", server_addr);
     loop {
         let (incoming_socket, _) = listener.accept().await.unwrap();
-        let authenticator_ref = authenticator.make();
-        let processor_ref = processor.make();
-        let placeholder_ref = placeholder.make();
+        let cancellation_registry = cancellation_registry.clone();
         tokio::spawn(async move {
-            process_socket(incoming_socket, None, authenticator_ref).await;
+            let (client, connection) = match tokio_postgres::connect(UPSTREAM_DSN, NoTls).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    eprintln!("Cannot open upstream connection: {}", e);
+                    return;
+                }
+            };
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    eprintln!("Upstream connection error: {}", e);
+                }
+            });
+
+            let factory = Arc::new(ProxyHandlerFactory {
+                processor: Arc::new(ProxyProcessor::new(client)),
+                cancellation_registry,
+            });
+            process_socket(incoming_socket, None, factory).await;
         });
     }
 }
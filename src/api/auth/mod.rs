@@ -0,0 +1,64 @@
+//! Startup and authentication handling.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use futures::Sink;
+
+use crate::api::ClientInfo;
+use crate::error::PgWireResult;
+use crate::messages::PgWireBackendMessage;
+
+pub mod noop;
+
+/// Information extracted from the frontend's startup packet.
+#[derive(Debug, Clone, Default)]
+pub struct LoginInfo {
+    pub user: Option<String>,
+    pub database: Option<String>,
+}
+
+/// Looks up credentials for a login, e.g. from a password file or an
+/// external identity provider.
+#[async_trait]
+pub trait AuthSource: Send + Sync {
+    async fn get_password(&self, login: &LoginInfo) -> PgWireResult<Vec<u8>>;
+}
+
+/// Supplies the server parameters a client would be sent as
+/// `ParameterStatus` messages after authentication. Nothing in this crate
+/// sends them: `messages` has no `ParameterStatus` message, and
+/// `process_socket` ends startup with `AuthenticationOk`, `BackendKeyData`
+/// and `ReadyForQuery` only.
+pub trait ServerParameterProvider: Send + Sync {
+    fn server_parameters<C>(&self, client: &C) -> HashMap<String, String>
+    where
+        C: ClientInfo;
+}
+
+/// A `ServerParameterProvider` that advertises a fixed, minimal parameter
+/// set.
+#[derive(Debug, Clone, Default)]
+pub struct DefaultServerParameterProvider;
+
+impl ServerParameterProvider for DefaultServerParameterProvider {
+    fn server_parameters<C>(&self, _client: &C) -> HashMap<String, String>
+    where
+        C: ClientInfo,
+    {
+        let mut params = HashMap::new();
+        params.insert("server_version".to_owned(), "14.0".to_owned());
+        params.insert("server_encoding".to_owned(), "UTF8".to_owned());
+        params.insert("client_encoding".to_owned(), "UTF8".to_owned());
+        params
+    }
+}
+
+/// Drives the startup/authentication handshake for a connection.
+#[async_trait]
+pub trait StartupHandler: Send + Sync {
+    async fn on_startup<C>(&self, client: &mut C) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: std::fmt::Debug;
+}
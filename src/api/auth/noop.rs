@@ -0,0 +1,24 @@
+use async_trait::async_trait;
+use futures::Sink;
+
+use crate::api::auth::StartupHandler;
+use crate::api::ClientInfo;
+use crate::error::PgWireResult;
+use crate::messages::PgWireBackendMessage;
+
+/// A `StartupHandler` that accepts every connection without checking
+/// credentials. Useful for proxies that delegate authentication to the
+/// upstream server.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopStartupHandler;
+
+#[async_trait]
+impl StartupHandler for NoopStartupHandler {
+    async fn on_startup<C>(&self, _client: &mut C) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: std::fmt::Debug,
+    {
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+//! Query cancellation: `BackendKeyData` issuance and `CancelRequest`
+//! handling.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+
+use crate::api::ClientInfo;
+use crate::error::PgWireResult;
+
+/// The `(process_id, secret_key)` pair sent to the client as
+/// `BackendKeyData` right after authentication, and later replayed back
+/// on a separate connection's `CancelRequest` to identify which running
+/// query to cancel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CancelToken {
+    pub process_id: i32,
+    pub secret_key: i32,
+}
+
+/// A type-erased handle back to one specific connection's `CancelHandler`,
+/// stored in a [`CancellationRegistry`] under its `CancelToken::process_id`.
+///
+/// A `CancelRequest` always arrives on a brand new connection that has no
+/// handler instance of its own for the session it names -- the registry is
+/// what lets it reach the right one instead of acting on whatever handler
+/// the new connection happens to be built with.
+pub type Canceller = Box<dyn Fn() -> BoxFuture<'static, PgWireResult<()>> + Send + Sync>;
+
+/// Issues and looks up [`CancelToken`]s for in-progress connections.
+///
+/// One registry is shared across every connection spawned from a
+/// `PgWireHandlerFactory`: each connection registers itself on startup to
+/// obtain the token it reports as `BackendKeyData`, attaches a
+/// [`Canceller`] that reaches its own `CancelHandler`, and deregisters on
+/// disconnect.
+pub trait CancellationRegistry: Send + Sync {
+    /// Registers a new connection, returning the `CancelToken` to send it
+    /// as `BackendKeyData`.
+    fn register(&self) -> CancelToken;
+
+    /// Removes a connection's entry once it disconnects.
+    fn deregister(&self, token: CancelToken);
+
+    /// Checks whether `token` matches a connection this registry knows
+    /// about, as required before acting on a `CancelRequest`.
+    fn verify(&self, token: CancelToken) -> bool;
+
+    /// Attaches `canceller` to the connection that previously registered
+    /// under `process_id`, so a later `CancelRequest` naming it can be
+    /// routed to the right connection's own `CancelHandler`.
+    fn set_canceller(&self, process_id: i32, canceller: Canceller);
+
+    /// Looks up and invokes the `Canceller` registered for `process_id`,
+    /// if that connection is still live and has attached one.
+    fn cancel(&self, process_id: i32) -> Option<BoxFuture<'static, PgWireResult<()>>>;
+}
+
+/// One connection's entry in a [`MemCancellationRegistry`]: the secret key
+/// to check a `CancelRequest` against, and the `Canceller` reaching back to
+/// that connection, once attached.
+#[derive(Default)]
+struct Entry {
+    secret_key: i32,
+    canceller: Option<Canceller>,
+}
+
+/// An in-memory [`CancellationRegistry`] backed by a process-id counter
+/// and a map of outstanding connection entries.
+#[derive(Default)]
+pub struct MemCancellationRegistry {
+    next_process_id: AtomicI32,
+    entries: Mutex<HashMap<i32, Entry>>,
+}
+
+impl std::fmt::Debug for MemCancellationRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemCancellationRegistry").finish_non_exhaustive()
+    }
+}
+
+impl MemCancellationRegistry {
+    pub fn new() -> MemCancellationRegistry {
+        MemCancellationRegistry::default()
+    }
+
+    /// A secret only the client that opened this connection should be able
+    /// to guess, so drawn from an RNG rather than anything correlated with
+    /// connection timing.
+    fn next_secret_key() -> i32 {
+        rand::random()
+    }
+}
+
+impl CancellationRegistry for MemCancellationRegistry {
+    fn register(&self) -> CancelToken {
+        let process_id = self.next_process_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let secret_key = Self::next_secret_key();
+        self.entries.lock().unwrap().insert(
+            process_id,
+            Entry {
+                secret_key,
+                canceller: None,
+            },
+        );
+        CancelToken {
+            process_id,
+            secret_key,
+        }
+    }
+
+    fn deregister(&self, token: CancelToken) {
+        self.entries.lock().unwrap().remove(&token.process_id);
+    }
+
+    fn verify(&self, token: CancelToken) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&token.process_id)
+            .is_some_and(|entry| entry.secret_key == token.secret_key)
+    }
+
+    fn set_canceller(&self, process_id: i32, canceller: Canceller) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&process_id) {
+            entry.canceller = Some(canceller);
+        }
+    }
+
+    fn cancel(&self, process_id: i32) -> Option<BoxFuture<'static, PgWireResult<()>>> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&process_id).and_then(|entry| entry.canceller.as_ref()).map(|canceller| canceller())
+    }
+}
+
+/// Handles an incoming `CancelRequest`, once its `CancelToken` has been
+/// validated against the [`CancellationRegistry`].
+#[async_trait]
+pub trait CancelHandler: Send + Sync {
+    async fn on_cancel<C>(&self, client: &C, token: CancelToken) -> PgWireResult<()>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+}
+
+/// A `CancelHandler` for servers that do not support cancellation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopCancelHandler;
+
+#[async_trait]
+impl CancelHandler for NoopCancelHandler {
+    async fn on_cancel<C>(&self, _client: &C, _token: CancelToken) -> PgWireResult<()>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn verify_accepts_only_the_registered_secret() {
+        let registry = MemCancellationRegistry::new();
+        let token = registry.register();
+
+        assert!(registry.verify(token));
+        assert!(!registry.verify(CancelToken {
+            process_id: token.process_id,
+            secret_key: token.secret_key.wrapping_add(1),
+        }));
+        assert!(!registry.verify(CancelToken {
+            process_id: token.process_id + 1,
+            secret_key: token.secret_key,
+        }));
+    }
+
+    #[test]
+    fn deregister_forgets_both_the_secret_and_the_canceller() {
+        let registry = MemCancellationRegistry::new();
+        let token = registry.register();
+        registry.set_canceller(token.process_id, Box::new(|| Box::pin(async { Ok(()) })));
+
+        registry.deregister(token);
+
+        assert!(!registry.verify(token));
+        assert!(registry.cancel(token.process_id).is_none());
+    }
+
+    #[test]
+    fn cancel_invokes_the_canceller_registered_for_that_process_id() {
+        let registry = MemCancellationRegistry::new();
+        let token_a = registry.register();
+        let token_b = registry.register();
+
+        let a_called = Arc::new(AtomicBool::new(false));
+        let a_called_in_closure = a_called.clone();
+        registry.set_canceller(
+            token_a.process_id,
+            Box::new(move || {
+                let a_called = a_called_in_closure.clone();
+                Box::pin(async move {
+                    a_called.store(true, Ordering::SeqCst);
+                    Ok(())
+                })
+            }),
+        );
+
+        // `token_b` never had a canceller attached, so looking it up
+        // should not find `token_a`'s.
+        assert!(registry.cancel(token_b.process_id).is_none());
+
+        let cancel = registry.cancel(token_a.process_id).expect("canceller was registered");
+        futures::executor::block_on(cancel).unwrap();
+        assert!(a_called.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,126 @@
+//! `COPY` protocol support: streaming `COPY ... FROM STDIN` data to a
+//! handler and streaming `COPY ... TO STDOUT` data back to the client.
+
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use futures::Stream;
+
+use crate::api::results::{FieldFormat, Tag};
+use crate::api::ClientInfo;
+use crate::error::{ErrorInfo, PgWireResult, SqlState};
+
+/// The response preceding a `COPY` data stream: the overall format and,
+/// for binary copies, the format of each column.
+#[derive(Debug, Clone)]
+pub struct CopyResponse {
+    pub format: FieldFormat,
+    pub column_formats: Vec<FieldFormat>,
+}
+
+impl CopyResponse {
+    pub fn new(format: FieldFormat, column_formats: Vec<FieldFormat>) -> CopyResponse {
+        CopyResponse {
+            format,
+            column_formats,
+        }
+    }
+}
+
+/// A stream of `CopyData` payloads, as produced by `COPY ... TO STDOUT`.
+pub type CopyDataStream<'a> = Pin<Box<dyn Stream<Item = PgWireResult<Vec<u8>>> + Send + 'a>>;
+
+/// Handles `COPY ... FROM STDIN` / `COPY ... TO STDOUT`.
+///
+/// A `COPY FROM STDIN` is driven by the connection's message loop: once a
+/// `Query` is recognized as a copy-in, [`copy_in_response`](CopyHandler::copy_in_response)
+/// opens it and the connection enters
+/// [`crate::api::PgWireConnectionState::CopyInProgress`]; every `CopyData`
+/// frame received is then handed to [`on_copy_data`](CopyHandler::on_copy_data),
+/// `CopyDone` finalizes it via [`on_copy_done`](CopyHandler::on_copy_done)
+/// and `CopyFail` aborts it via [`on_copy_fail`](CopyHandler::on_copy_fail).
+/// A `COPY ... TO STDOUT` is driven by [`copy_out`](CopyHandler::copy_out),
+/// which hands back the whole response (header plus row stream) up front.
+#[async_trait]
+pub trait CopyHandler: Send + Sync {
+    /// Starts a `COPY ... FROM STDIN` for `query`, returning the response
+    /// header to send the client before any `CopyData` frame arrives.
+    async fn copy_in_response<C>(&self, client: &C, query: &str) -> PgWireResult<CopyResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+
+    /// Called once per `CopyData` frame received from the client during
+    /// `COPY ... FROM STDIN`.
+    async fn on_copy_data<C>(&self, client: &C, data: Vec<u8>) -> PgWireResult<()>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+
+    /// Called when the client sends `CopyDone`, finalizing the copy-in
+    /// and returning the command tag to report back.
+    async fn on_copy_done<C>(&self, client: &C) -> PgWireResult<Tag>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+
+    /// Called when the client aborts with `CopyFail`. Per the protocol, a
+    /// `CopyFail` always results in an `ErrorResponse` to the client, so
+    /// this returns the `ErrorInfo` to report rather than a `PgWireResult`.
+    async fn on_copy_fail<C>(&self, client: &C, message: String) -> ErrorInfo
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+
+    /// Starts a `COPY ... TO STDOUT` for `query`, returning the response
+    /// header and the stream of row data to relay to the client.
+    async fn copy_out<'a, C>(&self, client: &C, query: &'a str) -> PgWireResult<(CopyResponse, CopyDataStream<'a>)>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+}
+
+/// A `CopyHandler` for servers that do not support `COPY`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopCopyHandler;
+
+#[async_trait]
+impl CopyHandler for NoopCopyHandler {
+    async fn copy_in_response<C>(&self, _client: &C, _query: &str) -> PgWireResult<CopyResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        Err(crate::error::PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            SqlState::from("0A000"),
+            "COPY is not supported by this server".to_owned(),
+        ))))
+    }
+
+    async fn on_copy_data<C>(&self, _client: &C, _data: Vec<u8>) -> PgWireResult<()>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        Ok(())
+    }
+
+    async fn on_copy_done<C>(&self, _client: &C) -> PgWireResult<Tag>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        Ok(Tag::new_for_execution("COPY", Some(0)))
+    }
+
+    async fn on_copy_fail<C>(&self, _client: &C, message: String) -> ErrorInfo
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        ErrorInfo::new("ERROR".to_owned(), SqlState::from("57014"), message)
+    }
+
+    async fn copy_out<'a, C>(&self, _client: &C, _query: &'a str) -> PgWireResult<(CopyResponse, CopyDataStream<'a>)>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        Err(crate::error::PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            SqlState::from("0A000"),
+            "COPY is not supported by this server".to_owned(),
+        ))))
+    }
+}
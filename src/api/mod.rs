@@ -1,24 +1,12 @@
 //! APIs for building PostgreSQL compatible servers.
 
 use std::collections::HashMap;
-use std::fmt::Debug;
-use std::hash::Hasher;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use futures::Sink;
 pub use postgres_types::Type;
-use tokio_postgres::{Row, SimpleQueryMessage, SimpleQueryRow};
-use crate::api::auth::noop::NoopStartupHandler;
-use crate::api::copy::NoopCopyHandler;
-use crate::api::portal::Portal;
-use crate::api::query::{DataRowEncoder, PlaceholderExtendedQueryHandler, SimpleQueryHandler};
-use crate::api::results::{DescribePortalResponse, DescribeStatementResponse, FieldFormat, QueryResponse};
-use crate::api::stmt::StoredStatement;
-use crate::api::store::PortalStore;
-use crate::error::{PgWireError, PgWireResult};
-use crate::messages::PgWireBackendMessage;
 
 pub mod auth;
+pub mod cancel;
 pub mod copy;
 pub mod portal;
 pub mod query;
@@ -121,11 +109,16 @@ pub trait PgWireHandlerFactory {
     type SimpleQueryHandler: query::SimpleQueryHandler;
     type ExtendedQueryHandler: query::ExtendedQueryHandler;
     type CopyHandler: copy::CopyHandler;
+    type CancelHandler: cancel::CancelHandler;
 
     fn simple_query_handler(&self) -> Arc<Self::SimpleQueryHandler>;
     fn extended_query_handler(&self) -> Arc<Self::ExtendedQueryHandler>;
     fn startup_handler(&self) -> Arc<Self::StartupHandler>;
     fn copy_handler(&self) -> Arc<Self::CopyHandler>;
+    fn cancel_handler(&self) -> Arc<Self::CancelHandler>;
+    /// The registry every connection spawned from this factory registers
+    /// itself with on startup and consults when a `CancelRequest` arrives.
+    fn cancellation_registry(&self) -> Arc<dyn cancel::CancellationRegistry>;
 }
 
 impl<T> PgWireHandlerFactory for Arc<T>
@@ -136,6 +129,7 @@ where
     type SimpleQueryHandler = T::SimpleQueryHandler;
     type ExtendedQueryHandler = T::ExtendedQueryHandler;
     type CopyHandler = T::CopyHandler;
+    type CancelHandler = T::CancelHandler;
 
     fn simple_query_handler(&self) -> Arc<Self::SimpleQueryHandler> {
         (**self).simple_query_handler()
@@ -152,236 +146,41 @@ where
     fn copy_handler(&self) -> Arc<Self::CopyHandler> {
         (**self).copy_handler()
     }
+
+    fn cancel_handler(&self) -> Arc<Self::CancelHandler> {
+        (**self).cancel_handler()
+    }
+
+    fn cancellation_registry(&self) -> Arc<dyn cancel::CancellationRegistry> {
+        (**self).cancellation_registry()
+    }
 }
 
 // Adding the StatelessMakeHandler implementation
 
 pub struct StatelessMakeHandler<H> {
     handler: Arc<H>,
+    cancellation_registry: Arc<cancel::MemCancellationRegistry>,
 }
 
 impl<H> StatelessMakeHandler<H> {
     pub fn new(handler: Arc<H>) -> Self {
-        Self { handler }
+        Self {
+            handler,
+            cancellation_registry: Arc::new(cancel::MemCancellationRegistry::new()),
+        }
     }
 
     // Implement the necessary handler methods
     pub fn make(&self) -> Arc<H> {
         Arc::clone(&self.handler)
     }
-}
-
-pub struct ProxyProcessor {
-    upstream_client: tokio_postgres::Client,
-}
-
-#[async_trait::async_trait]
-impl query::SimpleQueryHandler for ProxyProcessor {
-    async fn do_query<'a, C>(
-        &self,
-        _client: &C,
-        query: &'a str,
-    ) -> PgWireResult<Vec<query::Response<'a>>>
-    where
-        C: ClientInfo + Unpin + Send + Sync,
-    {
-        let resp_msgs = self.upstream_client
-            .simple_query(query)
-            .await
-            .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-
-        let mut downstream_response = Vec::new();
-        let mut row_buf = Vec::new();
-
-        for resp in resp_msgs {
-            match resp {
-                SimpleQueryMessage::CommandComplete(count) => {
-                    if row_buf.is_empty() {
-                        downstream_response.push(query::Response::Execution(
-                            query::Tag::new_for_execution("", Some(count as usize)),
-                        ));
-                    } else {
-                        let query_response = encode_simple_query_response(&row_buf);
-                        downstream_response.push(query::Response::Query(query_response));
-                    }
-                }
-                SimpleQueryMessage::Row(row) => {
-                    row_buf.push(row); // Keep SimpleQueryRow as is
-                }
-                _ => {}
-            }
-        }
-
-        Ok(downstream_response)
-    }
-}
-
-// fn encode_simple_query_response(p0: &Vec<SimpleQueryRow>) -> Box<T> {
-//     todo!()
-// }
-#[async_trait::async_trait]
-impl query::SimpleQueryHandler for ProxyProcessor {
-    async fn do_query<'a, C>(
-        &self,
-        _client: &C,
-        query: &'a str,
-    ) -> PgWireResult<Vec<query::Response<'a>>>
-    where
-        C: ClientInfo + Unpin + Send + Sync,
-    {
-        // Use simple_query to get SimpleQueryMessages
-        let resp_msgs = self.upstream_client
-            .simple_query(query)
-            .await
-            .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-
-        let mut downstream_response = Vec::new();
-        let mut row_buf = Vec::new();
-
-        for resp in resp_msgs {
-            match resp {
-                SimpleQueryMessage::CommandComplete(count) => {
-                    if row_buf.is_empty() {
-                        downstream_response.push(query::Response::Execution(
-                            query::Tag::new_for_execution("", Some(count as usize)),
-                        ));
-                    } else {
-                        // Here, you might want to handle converting the SimpleQueryRow to a usable format
-                        let query_response = encode_simple_query_response(&row_buf);
-                        downstream_response.push(query::Response::Query(query_response));
-                    }
-                }
-                SimpleQueryMessage::Row(row) => {
-                    // Store SimpleQueryRow for later processing
-                    row_buf.push(row);
-                }
-                _ => {}
-            }
-        }
-
-        Ok(downstream_response)
-    }
-}
-fn encode_simple_query_response(rows: &[tokio_postgres::SimpleQueryRow]) -> query::QueryResponse {
-    let mut encoder = query::DataRowEncoder::new(/* Your schema here */);
-
-    for row in rows {
-        // Loop through each field in the SimpleQueryRow
-        for field in row {
-            // Assuming you know the type of field or it can be inferred
-            encoder.encode_field(field).unwrap(); // Make sure to handle the potential error
-        }
-    }
-
-    query::QueryResponse::new(Arc::new(vec![]), encoder.finish())
-}
-// This function encodes the SimpleQueryRows into a format that your Response can handle
-
-
-
-#[async_trait::async_trait]
-impl query::ExtendedQueryHandler for ProxyProcessor {
-    type Statement = String;
-    type QueryParser = ();
-
-    fn query_parser(&self) -> Arc<Self::QueryParser> {
-        todo!()
-    }
-
-    async fn do_describe_statement<C>(&self, client: &mut C, target: &StoredStatement<Self::Statement>) -> PgWireResult<DescribeStatementResponse>
-    where
-        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
-        C::PortalStore: PortalStore<Statement=Self::Statement>,
-        C::Error: Debug,
-        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>
-    {
-        todo!()
-    }
-
-    async fn do_describe_portal<C>(&self, client: &mut C, target: &Portal<Self::Statement>) -> PgWireResult<DescribePortalResponse>
-    where
-        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
-        C::PortalStore: PortalStore<Statement=Self::Statement>,
-        C::Error: Debug,
-        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>
-    {
-        todo!()
-    }
-
-    async fn do_query<'a, C>(
-        &self,
-        _client: &mut C,
-        portal: &'a query::Portal<Self::Statement>,
-        _max_rows: usize,
-    ) -> PgWireResult<query::Response<'a>>
-    where
-        C: ClientInfo + Unpin + Send + Sync,
-    {
-        let query = &portal.statement.statement;
-        let params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![]; // Extract params from the portal
-        let rows = self.upstream_client.query(query, &params).await.map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-
-        let query_response = encode_query_response(&rows);
-        Ok(query::Response::Query(query_response))
-    }
-}
-impl PgWireHandlerFactory for NoopStartupHandler {
-    type StartupHandler = NoopStartupHandler;
-    type SimpleQueryHandler = Arc<dyn SimpleQueryHandler + Send + Sync>;
-    type ExtendedQueryHandler = PlaceholderExtendedQueryHandler;
-    type CopyHandler = NoopCopyHandler;
-
-    fn simple_query_handler(&self) -> Arc<Self::SimpleQueryHandler> {
-        todo!()
-    }
-
-    // fn simple_query_handler(&self) -> Arc<Self::SimpleQueryHandler> {
-    //     // Arc::new(self.clone())
-    // }
-
-    fn extended_query_handler(&self) -> Arc<Self::ExtendedQueryHandler> {
-        Arc::new(PlaceholderExtendedQueryHandler)
-    }
-
-    fn startup_handler(&self) -> Arc<Self::StartupHandler> {
-        Arc::new(NoopStartupHandler)
-    }
-
-    fn copy_handler(&self) -> Arc<Self::CopyHandler> {
-        Arc::new(NoopCopyHandler)
-    }
-}
-fn encode_query_response(rows: &Vec<Row>) -> QueryResponse {
-    let schema = vec![
-        // Define the schema according to your data structure
-        ("column1", FieldFormat::Text),
-        ("column2", FieldFormat::Text),
-        // Add more columns if needed
-    ];
-
-    let mut encoder = DataRowEncoder::new();
-
-    for row in rows {
-        for (index, (_col_name, _format)) in schema.iter().enumerate() {
-            // Access row data using the index, and encode each field.
-            // Assuming fields are of type String, adjust the parsing based on actual types.
-            if let Some(value) = row.get(index) {
-                encoder.encode_field(&value).unwrap();
-            } else {
-                // Handle NULL values
-                encoder.encode_field(&None::<String>).unwrap();
-            }
-        }
-    }
-
-    // Construct the QueryResponse from the encoded rows
-    let rows_encoded = encoder.finish();
-    QueryResponse::new(schema, rows_encoded)
-}
-pub(crate) struct DataRowEncoder();
 
-impl DataRowEncoder {
-    pub(crate) fn new() -> Box<T> {
-        todo!()
+    /// The cancellation registry shared by every connection spawned from
+    /// this factory, so a `PgWireHandlerFactory` built around [`Self::make`]
+    /// can route `BackendKeyData` issuance and `CancelRequest` lookups
+    /// through a single, connection-spanning table.
+    pub fn cancellation_registry(&self) -> Arc<cancel::MemCancellationRegistry> {
+        Arc::clone(&self.cancellation_registry)
     }
 }
@@ -0,0 +1,185 @@
+//! Portals: a bound, ready-to-execute instance of a prepared statement.
+
+use bytes::BytesMut;
+use postgres_types::{IsNull, ToSql, Type};
+use tokio_postgres::types::Format;
+
+use crate::api::results::FieldFormat;
+use crate::api::stmt::StoredStatement;
+use crate::error::{PgWireError, PgWireResult};
+use crate::messages::Bind;
+
+/// Expands the wire protocol's compact format-code encoding (0, 1 or N
+/// codes for N fields) into one [`FieldFormat`] per field.
+///
+/// Per the frontend/backend protocol, a format-code array may have length
+/// 0 (every field is text), 1 (the single code applies to every field) or
+/// exactly `count` (one code per field); any other length is a protocol
+/// violation.
+pub struct FormatIterator<'a> {
+    codes: &'a [i16],
+    index: usize,
+    count: usize,
+}
+
+impl<'a> FormatIterator<'a> {
+    pub fn new(codes: &'a [i16], count: usize) -> PgWireResult<FormatIterator<'a>> {
+        if !(codes.is_empty() || codes.len() == 1 || codes.len() == count) {
+            return Err(PgWireError::InvalidProtocolMessage(format!(
+                "format code count {} does not match field count {}",
+                codes.len(),
+                count
+            )));
+        }
+
+        Ok(FormatIterator {
+            codes,
+            index: 0,
+            count,
+        })
+    }
+}
+
+impl<'a> Iterator for FormatIterator<'a> {
+    type Item = FieldFormat;
+
+    fn next(&mut self) -> Option<FieldFormat> {
+        if self.index >= self.count {
+            return None;
+        }
+
+        let code = match self.codes.len() {
+            0 => 0,
+            1 => self.codes[0],
+            _ => self.codes[self.index],
+        };
+        self.index += 1;
+        Some(FieldFormat::from(code))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.count - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+/// A decoded parameter value paired with the format it was bound in.
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub format: FieldFormat,
+    pub value: Option<Vec<u8>>,
+}
+
+/// A `Bind` of parameter values onto a [`StoredStatement`], ready for
+/// `Execute`.
+///
+/// `result_column_format_codes` is kept in its raw, un-expanded form (the
+/// same 0/1/N array `Bind` carried it in), since the number of result
+/// columns isn't known until the statement's row shape is -- expand it
+/// with [`Portal::result_column_formats`] once that count is available.
+#[derive(Debug, Clone)]
+pub struct Portal<S> {
+    pub name: String,
+    pub statement: StoredStatement<S>,
+    pub parameters: Vec<Parameter>,
+    pub result_column_format_codes: Vec<i16>,
+}
+
+impl<S> Portal<S> {
+    /// Build a portal from a decoded `Bind` message, validating and
+    /// expanding its parameter format-code array via [`FormatIterator`].
+    pub fn try_new(name: String, statement: StoredStatement<S>, bind: &Bind) -> PgWireResult<Portal<S>> {
+        let param_formats =
+            FormatIterator::new(&bind.parameter_format_codes, bind.parameters.len())?.collect::<Vec<_>>();
+        let parameters = bind
+            .parameters
+            .iter()
+            .zip(param_formats)
+            .map(|(value, format)| Parameter {
+                format,
+                value: value.clone(),
+            })
+            .collect();
+
+        Ok(Portal {
+            name,
+            statement,
+            parameters,
+            result_column_format_codes: bind.result_column_format_codes.clone(),
+        })
+    }
+
+    /// Expand this portal's result format-code array into one
+    /// [`FieldFormat`] per result column, given the number of columns the
+    /// statement produces.
+    pub fn result_column_formats(&self, column_count: usize) -> PgWireResult<Vec<FieldFormat>> {
+        FormatIterator::new(&self.result_column_format_codes, column_count).map(Iterator::collect)
+    }
+}
+
+/// Adapts a raw, already wire-encoded parameter value (as received in
+/// `Bind`) into a `ToSql` implementor that forwards the bytes verbatim to
+/// the upstream driver instead of re-encoding them, preserving the
+/// original text/binary format.
+#[derive(Debug)]
+pub struct RawParameter {
+    pub format: FieldFormat,
+    pub value: Option<Vec<u8>>,
+}
+
+impl ToSql for RawParameter {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match &self.value {
+            Some(bytes) => {
+                out.extend_from_slice(bytes);
+                Ok(IsNull::No)
+            }
+            None => Ok(IsNull::Yes),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    fn encode_format(&self, _ty: &Type) -> Format {
+        match self.format {
+            FieldFormat::Text => Format::Text,
+            FieldFormat::Binary => Format::Binary,
+        }
+    }
+
+    postgres_types::to_sql_checked!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_iterator_defaults_every_field_to_text_when_empty() {
+        let formats = FormatIterator::new(&[], 3).unwrap().collect::<Vec<_>>();
+        assert_eq!(formats, vec![FieldFormat::Text; 3]);
+    }
+
+    #[test]
+    fn format_iterator_applies_a_single_code_to_every_field() {
+        let formats = FormatIterator::new(&[1], 3).unwrap().collect::<Vec<_>>();
+        assert_eq!(formats, vec![FieldFormat::Binary; 3]);
+    }
+
+    #[test]
+    fn format_iterator_applies_one_code_per_field() {
+        let formats = FormatIterator::new(&[0, 1], 2).unwrap().collect::<Vec<_>>();
+        assert_eq!(formats, vec![FieldFormat::Text, FieldFormat::Binary]);
+    }
+
+    #[test]
+    fn format_iterator_rejects_a_mismatched_code_count() {
+        assert!(FormatIterator::new(&[0, 1], 3).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Simple and extended query protocol handlers.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::Sink;
+
+use crate::api::portal::Portal;
+use crate::api::results::{DescribePortalResponse, DescribeStatementResponse, Response};
+use crate::api::stmt::StoredStatement;
+use crate::api::store::PortalStore;
+use crate::api::{ClientInfo, ClientPortalStore};
+use crate::error::{ErrorInfo, PgWireError, PgWireResult, SqlState};
+use crate::messages::PgWireBackendMessage;
+
+pub use crate::api::results::{DataRowEncoder, QueryResponse, Tag};
+
+/// Handles queries sent over the simple query protocol (a `Query`
+/// message carrying one or more semicolon-separated statements).
+#[async_trait]
+pub trait SimpleQueryHandler: Send + Sync {
+    async fn do_query<'a, C>(&self, client: &C, query: &'a str) -> PgWireResult<Vec<Response<'a>>>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+}
+
+/// Parses raw SQL text into the handler's statement representation, once
+/// per `Parse` message.
+#[async_trait]
+pub trait QueryParser: Send + Sync {
+    type Statement;
+
+    async fn parse_sql(&self, sql: &str) -> PgWireResult<Self::Statement>;
+}
+
+/// Handles the extended query protocol: `Parse`/`Bind`/`Describe`/
+/// `Execute`/`Sync`.
+#[async_trait]
+pub trait ExtendedQueryHandler: Send + Sync {
+    type Statement: Send + Sync;
+    type QueryParser: QueryParser<Statement = Self::Statement> + Send + Sync;
+
+    fn query_parser(&self) -> Arc<Self::QueryParser>;
+
+    async fn do_describe_statement<C>(
+        &self,
+        client: &mut C,
+        target: &StoredStatement<Self::Statement>,
+    ) -> PgWireResult<DescribeStatementResponse>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>;
+
+    async fn do_describe_portal<C>(
+        &self,
+        client: &mut C,
+        target: &Portal<Self::Statement>,
+    ) -> PgWireResult<DescribePortalResponse>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>;
+
+    async fn do_query<'a, C>(
+        &self,
+        client: &mut C,
+        portal: &'a Portal<Self::Statement>,
+        max_rows: usize,
+    ) -> PgWireResult<Response<'a>>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+}
+
+/// An `ExtendedQueryHandler` for servers that only implement the simple
+/// query protocol: `Describe` and `Execute` are rejected with
+/// `feature_not_supported`. `Parse` is still accepted through
+/// [`PlaceholderQueryParser`], and so is a `Bind` of a parsed statement.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlaceholderExtendedQueryHandler;
+
+fn extended_query_not_supported() -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        SqlState::FeatureNotSupported,
+        "extended query protocol is not supported by this server".to_owned(),
+    )))
+}
+
+#[async_trait]
+impl ExtendedQueryHandler for PlaceholderExtendedQueryHandler {
+    type Statement = String;
+    type QueryParser = PlaceholderQueryParser;
+
+    fn query_parser(&self) -> Arc<Self::QueryParser> {
+        Arc::new(PlaceholderQueryParser)
+    }
+
+    async fn do_describe_statement<C>(
+        &self,
+        _client: &mut C,
+        _target: &StoredStatement<Self::Statement>,
+    ) -> PgWireResult<DescribeStatementResponse>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        Err(extended_query_not_supported())
+    }
+
+    async fn do_describe_portal<C>(
+        &self,
+        _client: &mut C,
+        _target: &Portal<Self::Statement>,
+    ) -> PgWireResult<DescribePortalResponse>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        Err(extended_query_not_supported())
+    }
+
+    async fn do_query<'a, C>(
+        &self,
+        _client: &mut C,
+        _portal: &'a Portal<Self::Statement>,
+        _max_rows: usize,
+    ) -> PgWireResult<Response<'a>>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        Err(extended_query_not_supported())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlaceholderQueryParser;
+
+#[async_trait]
+impl QueryParser for PlaceholderQueryParser {
+    type Statement = String;
+
+    async fn parse_sql(&self, sql: &str) -> PgWireResult<Self::Statement> {
+        Ok(sql.to_owned())
+    }
+}
@@ -0,0 +1,500 @@
+//! Types describing the results of a query: row schema, encoded rows and
+//! the `Describe` responses that precede them.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::Stream;
+pub use postgres_types::Type;
+
+use crate::api::portal::FormatIterator;
+use crate::error::{PgWireError, PgWireResult};
+use crate::messages::PgWireBackendMessage;
+
+/// Wire format a field's value is encoded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldFormat {
+    Text,
+    Binary,
+}
+
+impl From<i16> for FieldFormat {
+    fn from(code: i16) -> FieldFormat {
+        if code == 0 {
+            FieldFormat::Text
+        } else {
+            FieldFormat::Binary
+        }
+    }
+}
+
+impl From<FieldFormat> for i16 {
+    fn from(format: FieldFormat) -> i16 {
+        match format {
+            FieldFormat::Text => 0,
+            FieldFormat::Binary => 1,
+        }
+    }
+}
+
+/// Describes a single result column: its name, source table/column (when
+/// known) and the Postgres type and wire format it will be sent in.
+#[derive(Debug, Clone)]
+pub struct FieldInfo {
+    pub name: String,
+    pub table_id: i32,
+    pub column_id: i16,
+    pub datatype: Type,
+    pub format: FieldFormat,
+}
+
+impl FieldInfo {
+    pub fn new(name: String, table_id: i32, column_id: i16, datatype: Type, format: FieldFormat) -> FieldInfo {
+        FieldInfo {
+            name,
+            table_id,
+            column_id,
+            datatype,
+            format,
+        }
+    }
+}
+
+/// Derive a row schema from a slice of `tokio_postgres::Column` metadata,
+/// the type shared by both `Row::columns()` and `Statement::columns()`.
+/// Every field is reported in `format`, the format the caller will
+/// actually send the column to the client in.
+pub fn schema_from_columns(columns: &[tokio_postgres::Column], format: FieldFormat) -> Vec<FieldInfo> {
+    columns
+        .iter()
+        .map(|col| FieldInfo::new(col.name().to_owned(), 0, 0, col.type_().clone(), format))
+        .collect()
+}
+
+/// Derive a row schema directly from a `tokio_postgres::Row`'s column
+/// metadata, so callers relaying upstream results don't have to hardcode
+/// one. Every field is reported in `format`, the format the caller will
+/// actually send the column to the client in.
+///
+/// Prefer [`schema_from_columns`] with the prepared statement's own
+/// `columns()` when one is available: a row-derived schema is empty for a
+/// query that legitimately matches zero rows.
+pub fn schema_from_row(row: &tokio_postgres::Row, format: FieldFormat) -> Vec<FieldInfo> {
+    schema_from_columns(row.columns(), format)
+}
+
+/// A single encoded row of query results, ready to be framed as a
+/// `DataRow` message.
+#[derive(Debug, Clone, Default)]
+pub struct DataRow {
+    pub(crate) fields: Vec<Option<Vec<u8>>>,
+}
+
+/// Builds a [`DataRow`] one field at a time, in schema order.
+pub struct DataRowEncoder {
+    schema: Arc<Vec<FieldInfo>>,
+    col_index: usize,
+    fields: Vec<Option<Vec<u8>>>,
+}
+
+impl DataRowEncoder {
+    pub fn new(schema: Arc<Vec<FieldInfo>>) -> DataRowEncoder {
+        DataRowEncoder {
+            schema,
+            col_index: 0,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Encode the next field in schema order from a displayable value,
+    /// text-encoding it regardless of the column's requested format.
+    pub fn encode_field<T>(&mut self, value: &Option<T>) -> PgWireResult<()>
+    where
+        T: std::fmt::Display,
+    {
+        self.fields.push(value.as_ref().map(|v| v.to_string().into_bytes()));
+        self.col_index += 1;
+        Ok(())
+    }
+
+    /// Encode the next field by pulling it straight out of an upstream
+    /// `tokio_postgres::Row`. `upstream_format` is the format the
+    /// upstream server actually sent this column in (`tokio_postgres`
+    /// always requests binary). When that matches the column's requested
+    /// output format the wire bytes are forwarded verbatim; otherwise the
+    /// value is decoded and re-encoded in the requested format. Only
+    /// binary-to-text re-encoding is implemented, for bool, the integer and
+    /// float types, text-like types, bytea, uuid, numeric and timestamps;
+    /// text-to-binary, or any other column type, returns an error.
+    pub fn encode_field_from_row(
+        &mut self,
+        row: &tokio_postgres::Row,
+        idx: usize,
+        upstream_format: FieldFormat,
+    ) -> PgWireResult<()> {
+        let field = &self.schema[self.col_index];
+
+        let raw: Option<RawBytes> = row
+            .try_get(idx)
+            .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
+
+        let encoded = match raw {
+            None => None,
+            Some(RawBytes(bytes)) if field.format == upstream_format => Some(bytes),
+            Some(RawBytes(bytes)) => Some(reencode::convert(&field.datatype, upstream_format, field.format, &bytes)?),
+        };
+
+        self.fields.push(encoded);
+        self.col_index += 1;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> PgWireResult<DataRow> {
+        debug_assert_eq!(self.col_index, self.schema.len());
+        Ok(DataRow {
+            fields: std::mem::take(&mut self.fields),
+        })
+    }
+}
+
+/// The result of a query that returns rows: the row schema plus a stream
+/// of encoded rows.
+pub struct QueryResponse<'a> {
+    pub row_schema: Arc<Vec<FieldInfo>>,
+    pub data_rows: Pin<Box<dyn Stream<Item = PgWireResult<DataRow>> + Send + 'a>>,
+}
+
+impl<'a> QueryResponse<'a> {
+    pub fn new<S>(row_schema: Arc<Vec<FieldInfo>>, data_rows: S) -> QueryResponse<'a>
+    where
+        S: Stream<Item = PgWireResult<DataRow>> + Send + 'a,
+    {
+        QueryResponse {
+            row_schema,
+            data_rows: Box::pin(data_rows),
+        }
+    }
+}
+
+/// The command tag sent back after a statement that does not return rows
+/// (e.g. `INSERT`, `UPDATE`, `DELETE`).
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+    pub rows: Option<usize>,
+}
+
+impl Tag {
+    pub fn new_for_execution(name: &str, rows: Option<usize>) -> Tag {
+        Tag {
+            name: name.to_owned(),
+            rows,
+        }
+    }
+}
+
+/// A stream of already-framed backend messages, as produced by a
+/// [`Response::PassThrough`].
+pub type PassThroughStream<'a> = Pin<Box<dyn Stream<Item = PgWireResult<PgWireBackendMessage>> + Send + 'a>>;
+
+/// What a query handler produces for a single statement.
+pub enum Response<'a> {
+    Query(QueryResponse<'a>),
+    Execution(Tag),
+    /// Already-framed backend messages ([`PgWireBackendMessage::RowDescription`],
+    /// [`PgWireBackendMessage::DataRow`], [`PgWireBackendMessage::CommandComplete`],
+    /// etc.) to forward to the client verbatim, bypassing `DataRowEncoder`
+    /// entirely. Opt-in for handlers -- such as a raw proxy relaying a
+    /// `tokio_postgres` message stream -- that want to preserve upstream
+    /// wire bytes exactly rather than decode and re-encode them.
+    PassThrough(PassThroughStream<'a>),
+}
+
+/// Answer to `Describe` for a statement: its parameter types and the
+/// shape of the rows it will produce, if any.
+#[derive(Debug, Clone)]
+pub struct DescribeStatementResponse {
+    pub parameter_types: Vec<Type>,
+    pub fields: Vec<FieldInfo>,
+}
+
+impl DescribeStatementResponse {
+    pub fn new(parameter_types: Vec<Type>, fields: Vec<FieldInfo>) -> DescribeStatementResponse {
+        DescribeStatementResponse {
+            parameter_types,
+            fields,
+        }
+    }
+}
+
+/// Answer to `Describe` for a portal: the shape of the rows it will
+/// produce.
+#[derive(Debug, Clone)]
+pub struct DescribePortalResponse {
+    pub fields: Vec<FieldInfo>,
+}
+
+impl DescribePortalResponse {
+    pub fn new(fields: Vec<FieldInfo>) -> DescribePortalResponse {
+        DescribePortalResponse { fields }
+    }
+
+    /// Apply the client's requested per-column result formats (from the
+    /// `Bind` message's result-format-code array) to this response's
+    /// fields, expanding the array with the same rules `Bind` parameter
+    /// formats follow.
+    pub fn with_result_formats(mut self, raw_codes: &[i16]) -> PgWireResult<DescribePortalResponse> {
+        let formats = FormatIterator::new(raw_codes, self.fields.len())?;
+        for (field, format) in self.fields.iter_mut().zip(formats) {
+            field.format = format;
+        }
+        Ok(self)
+    }
+}
+
+/// Captures a column's exact wire bytes without interpreting them, by
+/// accepting every Postgres type and handing back the raw `FromSql`
+/// buffer untouched.
+struct RawBytes(Vec<u8>);
+
+impl<'a> postgres_types::FromSql<'a> for RawBytes {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<RawBytes, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(RawBytes(raw.to_vec()))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+/// Converts a column's raw wire bytes between formats for the handful of
+/// types `encode_field_from_row` needs to support.
+mod reencode {
+    use super::{FieldFormat, PgWireError, PgWireResult, Type};
+
+    pub(super) fn convert(ty: &Type, from: FieldFormat, to: FieldFormat, bytes: &[u8]) -> PgWireResult<Vec<u8>> {
+        match (from, to) {
+            (FieldFormat::Binary, FieldFormat::Text) => binary_to_text(ty, bytes),
+            // Encoding text back to binary isn't implemented -- forwarding
+            // the bytes unconverted would mislabel them as binary and
+            // silently corrupt whatever reads them, so refuse instead.
+            (FieldFormat::Text, FieldFormat::Binary) => Err(PgWireError::InvalidProtocolMessage(format!(
+                "cannot re-encode column of type {} from text to binary",
+                ty
+            ))),
+            // Same format in and out: nothing to convert.
+            (FieldFormat::Text, FieldFormat::Text) | (FieldFormat::Binary, FieldFormat::Binary) => {
+                Ok(bytes.to_vec())
+            }
+        }
+    }
+
+    fn binary_to_text(ty: &Type, raw: &[u8]) -> PgWireResult<Vec<u8>> {
+        let text = match *ty {
+            Type::BOOL => if raw.first() == Some(&1) { "t" } else { "f" }.to_owned(),
+            Type::INT2 => i16::from_be_bytes(fixed(ty, raw)?).to_string(),
+            Type::INT4 => i32::from_be_bytes(fixed(ty, raw)?).to_string(),
+            Type::INT8 => i64::from_be_bytes(fixed(ty, raw)?).to_string(),
+            Type::FLOAT4 => f32::from_be_bytes(fixed(ty, raw)?).to_string(),
+            Type::FLOAT8 => f64::from_be_bytes(fixed(ty, raw)?).to_string(),
+            Type::TEXT | Type::VARCHAR | Type::NAME | Type::BPCHAR => String::from_utf8(raw.to_vec())
+                .map_err(|_| PgWireError::InvalidProtocolMessage(format!("invalid utf8 in {} column", ty)))?,
+            Type::BYTEA => format!("\\x{}", hex_encode(raw)),
+            Type::UUID => format_uuid(raw)?,
+            Type::NUMERIC => format_numeric(raw)?,
+            Type::TIMESTAMP | Type::TIMESTAMPTZ => format_timestamp(raw)?,
+            _ => {
+                return Err(PgWireError::InvalidProtocolMessage(format!(
+                    "cannot re-encode column of type {} from binary to text",
+                    ty
+                )))
+            }
+        };
+        Ok(text.into_bytes())
+    }
+
+    fn fixed<const N: usize>(ty: &Type, raw: &[u8]) -> PgWireResult<[u8; N]> {
+        raw.try_into()
+            .map_err(|_| PgWireError::InvalidProtocolMessage(format!("unexpected binary length for {} column", ty)))
+    }
+
+    fn hex_encode(bytes: &[u8]) -> String {
+        let mut out = String::with_capacity(bytes.len() * 2);
+        for b in bytes {
+            out.push_str(&format!("{:02x}", b));
+        }
+        out
+    }
+
+    fn format_uuid(raw: &[u8]) -> PgWireResult<String> {
+        if raw.len() != 16 {
+            return Err(PgWireError::InvalidProtocolMessage(
+                "uuid value is not 16 bytes".to_owned(),
+            ));
+        }
+        let hex = hex_encode(raw);
+        Ok(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        ))
+    }
+
+    /// Decodes the Postgres binary `numeric` wire format (a sequence of
+    /// base-10000 digit groups) into its canonical text representation.
+    fn format_numeric(raw: &[u8]) -> PgWireResult<String> {
+        if raw.len() < 8 {
+            return Err(PgWireError::InvalidProtocolMessage(
+                "numeric value is too short".to_owned(),
+            ));
+        }
+        let ndigits = u16::from_be_bytes([raw[0], raw[1]]) as usize;
+        let weight = i16::from_be_bytes([raw[2], raw[3]]) as i32;
+        let sign = u16::from_be_bytes([raw[4], raw[5]]);
+        let dscale = u16::from_be_bytes([raw[6], raw[7]]) as usize;
+
+        const NUMERIC_NEG: u16 = 0x4000;
+        const NUMERIC_NAN: u16 = 0xC000;
+        if sign == NUMERIC_NAN {
+            return Ok("NaN".to_owned());
+        }
+
+        let mut digits = Vec::with_capacity(ndigits);
+        for i in 0..ndigits {
+            let offset = 8 + i * 2;
+            let d = raw
+                .get(offset..offset + 2)
+                .ok_or_else(|| PgWireError::InvalidProtocolMessage("numeric value truncated".to_owned()))?;
+            digits.push(u16::from_be_bytes([d[0], d[1]]));
+        }
+
+        let mut out = String::new();
+        if sign == NUMERIC_NEG {
+            out.push('-');
+        }
+
+        // `digits` holds base-10000 groups; `weight` is the base-10000
+        // exponent of the first (most significant) group.
+        let int_groups = weight + 1;
+        if int_groups <= 0 {
+            out.push('0');
+        } else {
+            for i in 0..int_groups {
+                let digit = digits.get(i as usize).copied().unwrap_or(0);
+                if i == 0 {
+                    out.push_str(&digit.to_string());
+                } else {
+                    out.push_str(&format!("{:04}", digit));
+                }
+            }
+        }
+
+        if dscale > 0 {
+            out.push('.');
+            let mut fraction = String::new();
+            let frac_groups = dscale.div_ceil(4);
+            for i in 0..frac_groups as i32 {
+                let group_idx = int_groups + i;
+                let digit = if group_idx >= 0 {
+                    digits.get(group_idx as usize).copied().unwrap_or(0)
+                } else {
+                    0
+                };
+                fraction.push_str(&format!("{:04}", digit));
+            }
+            fraction.truncate(dscale);
+            out.push_str(&fraction);
+        }
+
+        Ok(out)
+    }
+
+    /// Decodes the Postgres binary `timestamp`/`timestamptz` wire format
+    /// (microseconds since 2000-01-01) into `YYYY-MM-DD HH:MM:SS.ffffff`.
+    fn format_timestamp(raw: &[u8]) -> PgWireResult<String> {
+        let micros_since_2000 = i64::from_be_bytes(fixed(&Type::TIMESTAMP, raw)?);
+        let days_since_2000 = micros_since_2000.div_euclid(86_400_000_000);
+        let mut micros_of_day = micros_since_2000.rem_euclid(86_400_000_000);
+        // civil_from_days operates on days since the Unix epoch.
+        let days_since_epoch = days_since_2000 + 10_957;
+
+        let (year, month, day) = civil_from_days(days_since_epoch);
+        let hours = micros_of_day / 3_600_000_000;
+        micros_of_day %= 3_600_000_000;
+        let minutes = micros_of_day / 60_000_000;
+        micros_of_day %= 60_000_000;
+        let seconds = micros_of_day / 1_000_000;
+        let micros = micros_of_day % 1_000_000;
+
+        Ok(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+            year, month, day, hours, minutes, seconds, micros
+        ))
+    }
+
+    /// Howard Hinnant's `civil_from_days`: days since the Unix epoch to a
+    /// (year, month, day) triple in the proleptic Gregorian calendar.
+    fn civil_from_days(z: i64) -> (i64, u32, u32) {
+        let z = z + 719_468;
+        let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+        let doe = (z - era * 146_097) as u64;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe as i64 + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn binary_to_text_decodes_the_handful_of_supported_types() {
+            assert_eq!(binary_to_text(&Type::BOOL, &[1]).unwrap(), b"t");
+            assert_eq!(binary_to_text(&Type::BOOL, &[0]).unwrap(), b"f");
+            assert_eq!(binary_to_text(&Type::INT4, &42i32.to_be_bytes()).unwrap(), b"42");
+            assert_eq!(binary_to_text(&Type::INT8, &(-7i64).to_be_bytes()).unwrap(), b"-7");
+            assert_eq!(binary_to_text(&Type::TEXT, b"hello").unwrap(), b"hello");
+            assert_eq!(binary_to_text(&Type::BYTEA, &[0xDE, 0xAD]).unwrap(), b"\\xdead");
+        }
+
+        #[test]
+        fn binary_to_text_rejects_an_unsupported_type() {
+            assert!(binary_to_text(&Type::JSON, b"{}").is_err());
+        }
+
+        #[test]
+        fn binary_to_text_rejects_a_truncated_fixed_width_value() {
+            assert!(binary_to_text(&Type::INT4, &[0, 0]).is_err());
+        }
+
+        #[test]
+        fn format_numeric_decodes_sign_weight_and_scale() {
+            // 123.45: ndigits=2, weight=0, sign=positive, dscale=2, digits=[123, 4500]
+            let raw = [0, 2, 0, 0, 0, 0, 0, 2, 0, 123, 17, 148];
+            assert_eq!(format_numeric(&raw).unwrap(), "123.45");
+
+            // -5: ndigits=1, weight=0, sign=negative, dscale=0, digits=[5]
+            let raw = [0, 1, 0, 0, 0x40, 0, 0, 0, 0, 5];
+            assert_eq!(format_numeric(&raw).unwrap(), "-5");
+        }
+
+        #[test]
+        fn format_numeric_reports_nan() {
+            let raw = [0, 0, 0, 0, 0xC0, 0, 0, 0];
+            assert_eq!(format_numeric(&raw).unwrap(), "NaN");
+        }
+
+        #[test]
+        fn format_timestamp_decodes_micros_since_2000() {
+            // 2000-01-01 00:00:00.000000 is epoch zero for this format.
+            assert_eq!(format_timestamp(&0i64.to_be_bytes()).unwrap(), "2000-01-01 00:00:00.000000");
+        }
+    }
+}
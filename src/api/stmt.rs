@@ -0,0 +1,25 @@
+//! Prepared statements stored server-side between `Parse` and `Bind`.
+
+/// A prepared statement named by the client and stored until it is bound
+/// into a portal or explicitly closed.
+#[derive(Debug, Clone)]
+pub struct StoredStatement<S> {
+    /// Name the client gave this statement, empty for the unnamed
+    /// statement.
+    pub id: String,
+    /// The parsed representation produced by the server's `QueryParser`.
+    pub statement: S,
+    /// The parameter types declared on `Parse`. A parameter declared with
+    /// OID `0` (unspecified) is stored as `Type::UNKNOWN`; nothing infers it.
+    pub parameter_types: Vec<crate::api::Type>,
+}
+
+impl<S> StoredStatement<S> {
+    pub fn new(id: String, statement: S, parameter_types: Vec<crate::api::Type>) -> StoredStatement<S> {
+        StoredStatement {
+            id,
+            statement,
+            parameter_types,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Server-side storage for prepared statements and portals.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::api::portal::Portal;
+use crate::api::stmt::StoredStatement;
+
+/// Keeps track of the named (and unnamed) statements and portals a client
+/// has created via the extended query protocol.
+pub trait PortalStore {
+    type Statement;
+
+    fn put_statement(&self, statement: StoredStatement<Self::Statement>);
+    fn get_statement(&self, name: &str) -> Option<StoredStatement<Self::Statement>>;
+    fn rm_statement(&self, name: &str);
+
+    fn put_portal(&self, portal: Portal<Self::Statement>);
+    fn get_portal(&self, name: &str) -> Option<Portal<Self::Statement>>;
+    fn rm_portal(&self, name: &str);
+}
+
+/// An in-memory `PortalStore`, keyed by statement/portal name.
+#[derive(Debug, Default)]
+pub struct MemPortalStore<S> {
+    statements: Mutex<HashMap<String, StoredStatement<S>>>,
+    portals: Mutex<HashMap<String, Portal<S>>>,
+}
+
+impl<S> MemPortalStore<S> {
+    pub fn new() -> MemPortalStore<S> {
+        MemPortalStore {
+            statements: Mutex::new(HashMap::new()),
+            portals: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S: Clone> PortalStore for MemPortalStore<S> {
+    type Statement = S;
+
+    fn put_statement(&self, statement: StoredStatement<S>) {
+        self.statements
+            .lock()
+            .unwrap()
+            .insert(statement.id.clone(), statement);
+    }
+
+    fn get_statement(&self, name: &str) -> Option<StoredStatement<S>> {
+        self.statements.lock().unwrap().get(name).cloned()
+    }
+
+    fn rm_statement(&self, name: &str) {
+        self.statements.lock().unwrap().remove(name);
+    }
+
+    fn put_portal(&self, portal: Portal<S>) {
+        self.portals.lock().unwrap().insert(portal.name.clone(), portal);
+    }
+
+    fn get_portal(&self, name: &str) -> Option<Portal<S>> {
+        self.portals.lock().unwrap().get(name).cloned()
+    }
+
+    fn rm_portal(&self, name: &str) {
+        self.portals.lock().unwrap().remove(name);
+    }
+}
@@ -0,0 +1,203 @@
+//! Error types shared by all pgwire handlers.
+
+use std::error::Error;
+use std::fmt;
+
+use tokio_postgres::error::DbError;
+
+use crate::messages::ErrorResponse;
+
+pub type PgWireResult<T> = Result<T, PgWireError>;
+
+/// Top-level error type returned by handler implementations.
+#[derive(Debug)]
+pub enum PgWireError {
+    /// A malformed or unexpected message was received from the client.
+    InvalidProtocolMessage(String),
+    /// A structured, SQLSTATE-bearing error to report back to the client
+    /// as an `ErrorResponse`.
+    UserError(Box<ErrorInfo>),
+    /// Catch-all for errors raised by the backing implementation (e.g. an
+    /// upstream database driver) that do not yet have a dedicated variant.
+    ApiError(Box<dyn Error + Sync + Send>),
+    /// Wraps an underlying IO failure.
+    IoError(std::io::Error),
+}
+
+impl fmt::Display for PgWireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgWireError::InvalidProtocolMessage(msg) => {
+                write!(f, "invalid protocol message: {}", msg)
+            }
+            PgWireError::UserError(info) => write!(f, "{}: {}", info.sqlstate.code(), info.message),
+            PgWireError::ApiError(e) => write!(f, "{}", e),
+            PgWireError::IoError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for PgWireError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PgWireError::ApiError(e) => Some(e.as_ref()),
+            PgWireError::IoError(e) => Some(e),
+            PgWireError::InvalidProtocolMessage(_) => None,
+            PgWireError::UserError(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PgWireError {
+    fn from(e: std::io::Error) -> Self {
+        PgWireError::IoError(e)
+    }
+}
+
+/// Translate an upstream `tokio_postgres` error into a `PgWireError`,
+/// preserving its SQLSTATE and message fields when the upstream failure
+/// came back from the server (as opposed to e.g. a connection error with
+/// no `DbError` attached).
+impl From<tokio_postgres::Error> for PgWireError {
+    fn from(e: tokio_postgres::Error) -> PgWireError {
+        match e.as_db_error() {
+            Some(db_error) => PgWireError::UserError(Box::new(ErrorInfo::from(db_error))),
+            None => PgWireError::ApiError(Box::new(e)),
+        }
+    }
+}
+
+/// The standard five-character SQLSTATE error codes, as defined by the
+/// PostgreSQL documentation. Not exhaustive: codes without a dedicated
+/// variant fall back to [`SqlState::Other`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SqlState {
+    SuccessfulCompletion,
+    FeatureNotSupported,
+    SyntaxError,
+    ConnectionFailure,
+    QueryCanceled,
+    Other(String),
+}
+
+const KNOWN_SQL_STATES: &[(&str, SqlState)] = &[
+    ("00000", SqlState::SuccessfulCompletion),
+    ("0A000", SqlState::FeatureNotSupported),
+    ("42601", SqlState::SyntaxError),
+    ("08006", SqlState::ConnectionFailure),
+    ("57014", SqlState::QueryCanceled),
+];
+
+impl SqlState {
+    /// The five-character SQLSTATE code for this variant.
+    pub fn code(&self) -> &str {
+        match self {
+            SqlState::Other(code) => code,
+            known => KNOWN_SQL_STATES
+                .iter()
+                .find(|(_, s)| s == known)
+                .map(|(code, _)| *code)
+                .expect("every non-Other variant has an entry in KNOWN_SQL_STATES"),
+        }
+    }
+}
+
+impl From<&str> for SqlState {
+    fn from(code: &str) -> SqlState {
+        KNOWN_SQL_STATES
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, s)| s.clone())
+            .unwrap_or_else(|| SqlState::Other(code.to_owned()))
+    }
+}
+
+/// The fields of an `ErrorResponse`/`NoticeResponse` message: severity,
+/// SQLSTATE and message are required, the rest are optional extra
+/// context.
+#[derive(Debug, Clone)]
+pub struct ErrorInfo {
+    pub severity: String,
+    pub sqlstate: SqlState,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    pub position: Option<u32>,
+}
+
+impl ErrorInfo {
+    pub fn new(severity: String, sqlstate: SqlState, message: String) -> ErrorInfo {
+        ErrorInfo {
+            severity,
+            sqlstate,
+            message,
+            detail: None,
+            hint: None,
+            position: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> ErrorInfo {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> ErrorInfo {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn with_position(mut self, position: u32) -> ErrorInfo {
+        self.position = Some(position);
+        self
+    }
+}
+
+impl From<&DbError> for ErrorInfo {
+    fn from(db_error: &DbError) -> ErrorInfo {
+        ErrorInfo {
+            severity: db_error.severity().to_owned(),
+            sqlstate: SqlState::from(db_error.code().code()),
+            message: db_error.message().to_owned(),
+            detail: db_error.detail().map(ToOwned::to_owned),
+            hint: db_error.hint().map(ToOwned::to_owned),
+            position: match db_error.position() {
+                Some(tokio_postgres::error::ErrorPosition::Original(pos)) => Some(*pos),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<ErrorInfo> for ErrorResponse {
+    fn from(info: ErrorInfo) -> ErrorResponse {
+        ErrorResponse {
+            severity: info.severity,
+            code: info.sqlstate.code().to_owned(),
+            message: info.message,
+            detail: info.detail,
+            hint: info.hint,
+            position: info.position,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_codes_round_trip_through_their_dedicated_variant() {
+        for (code, state) in KNOWN_SQL_STATES {
+            assert_eq!(&SqlState::from(*code), state);
+            assert_eq!(state.code(), *code);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_fall_back_to_other() {
+        let state = SqlState::from("26000");
+        assert_eq!(state, SqlState::Other("26000".to_owned()));
+        assert_eq!(state.code(), "26000");
+    }
+}
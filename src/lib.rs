@@ -0,0 +1,7 @@
+//! pgwire is a framework/toolkit to implement PostgreSQL compatible servers
+//! and proxies.
+
+pub mod api;
+pub mod error;
+pub mod messages;
+pub mod tokio;
@@ -0,0 +1,226 @@
+//! Frontend/backend wire message envelopes.
+//!
+//! This module only models the message shapes the `api` layer and the
+//! `tokio` connection driver need to reason about: the simple query
+//! protocol, the extended query protocol (`Parse`/`Bind`/`Describe`/
+//! `Execute`/`Sync`), `COPY` streaming and cancellation. It is not a full
+//! protocol codec -- e.g. `Close` and `Flush` are not modeled, since
+//! nothing in this crate needs to send or receive them yet.
+
+/// A message flowing from frontend to backend, as decoded off the wire by
+/// [`crate::tokio::process_socket`].
+#[derive(Debug, Clone)]
+pub enum PgWireFrontendMessage {
+    /// A `Query` message carrying one or more semicolon-separated
+    /// statements.
+    Query(String),
+    Parse(Parse),
+    Bind(Bind),
+    Describe(Describe),
+    Execute(Execute),
+    /// Asks the server to process everything sent so far and report
+    /// `ReadyForQuery`, ending an extended-protocol message group.
+    Sync,
+    CopyData(CopyData),
+    CopyDone(CopyDone),
+    CopyFail(CopyFail),
+    /// The client is closing the connection.
+    Terminate,
+}
+
+/// A message flowing from backend to frontend.
+#[derive(Debug, Clone)]
+pub enum PgWireBackendMessage {
+    AuthenticationOk(AuthenticationOk),
+    ErrorResponse(ErrorResponse),
+    NoticeResponse(ErrorResponse),
+    CopyInResponse(CopyInResponse),
+    CopyOutResponse(CopyOutResponse),
+    CopyData(CopyData),
+    CopyDone(CopyDone),
+    BackendKeyData(BackendKeyData),
+    ParseComplete(ParseComplete),
+    BindComplete(BindComplete),
+    ParameterDescription(ParameterDescription),
+    NoData(NoData),
+    RowDescription(RowDescription),
+    DataRow(DataRow),
+    CommandComplete(CommandComplete),
+    ReadyForQuery(ReadyForQuery),
+}
+
+/// Sent once authentication succeeds, before `BackendKeyData`. This crate
+/// only ships trust-style auth (see
+/// [`crate::api::auth::noop::NoopStartupHandler`]), so it carries no
+/// payload beyond the "ok" code.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AuthenticationOk;
+
+/// Sent once the server is ready to accept a new query: at the end of the
+/// startup sequence, after a simple-protocol command cycle completes, and
+/// in response to `Sync` in the extended query protocol. Never sent while
+/// a `COPY FROM STDIN` is in progress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadyForQuery;
+
+/// Severity + SQLSTATE + message fields carried by `ErrorResponse` and
+/// `NoticeResponse`.
+#[derive(Debug, Clone)]
+pub struct ErrorResponse {
+    pub severity: String,
+    pub code: String,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    pub position: Option<u32>,
+}
+
+impl ErrorResponse {
+    pub fn new(severity: String, code: String, message: String) -> ErrorResponse {
+        ErrorResponse {
+            severity,
+            code,
+            message,
+            detail: None,
+            hint: None,
+            position: None,
+        }
+    }
+}
+
+/// A decoded `Parse` message: prepares `query` under `name` (the unnamed
+/// statement when empty), with explicit parameter type OIDs where given --
+/// `0` means "let the parser infer it".
+#[derive(Debug, Clone, Default)]
+pub struct Parse {
+    pub name: String,
+    pub query: String,
+    pub parameter_type_oids: Vec<i32>,
+}
+
+/// A decoded `Bind` message from the extended query protocol.
+#[derive(Debug, Clone, Default)]
+pub struct Bind {
+    pub portal_name: String,
+    pub statement_name: String,
+    pub parameter_format_codes: Vec<i16>,
+    pub parameters: Vec<Option<Vec<u8>>>,
+    pub result_column_format_codes: Vec<i16>,
+}
+
+/// A decoded `Describe` message: which previously `Parse`d statement or
+/// `Bind`-produced portal the client wants described.
+#[derive(Debug, Clone)]
+pub enum Describe {
+    Statement(String),
+    Portal(String),
+}
+
+/// A decoded `Execute` message: run `name`'s portal (the unnamed portal
+/// when empty), returning at most `max_rows` rows (`0` means no limit).
+#[derive(Debug, Clone, Default)]
+pub struct Execute {
+    pub name: String,
+    pub max_rows: i32,
+}
+
+/// Sent before streaming `COPY ... FROM STDIN` data, announcing the
+/// overall copy format (0 text, 1 binary) and, for binary copies, each
+/// column's format.
+#[derive(Debug, Clone)]
+pub struct CopyInResponse {
+    pub overall_format: i8,
+    pub column_formats: Vec<i16>,
+}
+
+/// Sent before streaming `COPY ... TO STDOUT` data. Same shape as
+/// [`CopyInResponse`].
+#[derive(Debug, Clone)]
+pub struct CopyOutResponse {
+    pub overall_format: i8,
+    pub column_formats: Vec<i16>,
+}
+
+/// A single chunk of `COPY` data, carried by `CopyData` frames sent by
+/// either side: the client during `COPY FROM STDIN`, the backend during
+/// `COPY TO STDOUT`.
+#[derive(Debug, Clone, Default)]
+pub struct CopyData(pub Vec<u8>);
+
+/// Marks the end of a client-driven `COPY FROM STDIN` stream.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyDone;
+
+/// Aborts a client-driven `COPY FROM STDIN` stream with an error message.
+#[derive(Debug, Clone, Default)]
+pub struct CopyFail(pub String);
+
+/// Sent right after authentication succeeds, giving the client the
+/// `(process_id, secret_key)` pair to replay back in a `CancelRequest` on
+/// a separate connection to cancel this connection's running query.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackendKeyData {
+    pub process_id: i32,
+    pub secret_key: i32,
+}
+
+/// A decoded `CancelRequest` startup packet: a separate connection asking
+/// to cancel the query running on the connection identified by
+/// `process_id`/`secret_key`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CancelRequest {
+    pub process_id: i32,
+    pub secret_key: i32,
+}
+
+/// Sent in response to a `Parse` message once the statement is stored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseComplete;
+
+/// Sent in response to a `Bind` message once the portal is stored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BindComplete;
+
+/// Answer to `Describe` for a statement: the OIDs of its parameter types,
+/// in parameter-ordinal order.
+#[derive(Debug, Clone, Default)]
+pub struct ParameterDescription {
+    pub parameter_type_oids: Vec<i32>,
+}
+
+/// Sent instead of `RowDescription` when a `Describe`d statement or portal
+/// does not return rows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoData;
+
+/// Describes one column of a `RowDescription`, the message sent once
+/// before the `DataRow`s of a query result. Mirrors
+/// [`crate::api::results::FieldInfo`] in wire-frame form; kept separate so
+/// `messages` does not depend on `api`.
+#[derive(Debug, Clone)]
+pub struct RowDescriptionField {
+    pub name: String,
+    pub table_id: i32,
+    pub column_id: i16,
+    pub type_oid: i32,
+    pub format: i16,
+}
+
+/// Sent once before a query result's `DataRow`s, announcing the shape of
+/// every row that follows.
+#[derive(Debug, Clone, Default)]
+pub struct RowDescription {
+    pub fields: Vec<RowDescriptionField>,
+}
+
+/// A single row of query results, one value per `RowDescription` field in
+/// order. `None` marks a SQL `NULL`.
+#[derive(Debug, Clone, Default)]
+pub struct DataRow {
+    pub fields: Vec<Option<Vec<u8>>>,
+}
+
+/// Sent after a statement finishes, reporting the command tag (e.g.
+/// `"SELECT 3"`) shown to the client.
+#[derive(Debug, Clone, Default)]
+pub struct CommandComplete(pub String);
@@ -0,0 +1,1068 @@
+//! tokio-based socket driver for the pgwire protocol state machine.
+//!
+//! This models the wire framing the rest of the crate needs to exercise
+//! its handler traits end to end: the simple query protocol, the
+//! extended query protocol (`Parse`/`Bind`/`Describe`/`Execute`/`Sync`),
+//! `COPY` streaming, cancellation and `Response::PassThrough`. It is not
+//! a full protocol codec -- e.g. `Close` and `Flush` are not decoded,
+//! since nothing in this crate needs to send or receive them yet.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use futures::{Sink, SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use crate::api::auth::StartupHandler;
+use crate::api::cancel::{CancelHandler, CancelToken, CancellationRegistry};
+use crate::api::copy::CopyHandler;
+use crate::api::portal::Portal;
+use crate::api::query::{ExtendedQueryHandler, SimpleQueryHandler};
+use crate::api::results::{DescribePortalResponse, DescribeStatementResponse, PassThroughStream, QueryResponse, Response, Tag};
+use crate::api::stmt::StoredStatement;
+use crate::api::store::PortalStore;
+use crate::api::{ClientInfo, ClientPortalStore, DefaultClient, PgWireConnectionState, PgWireHandlerFactory};
+use crate::error::{ErrorInfo, PgWireError, PgWireResult, SqlState};
+use crate::messages::{
+    AuthenticationOk, BackendKeyData, Bind, CommandComplete, CopyData, CopyDone, CopyFail, CopyInResponse,
+    CopyOutResponse, DataRow, Describe, ErrorResponse, Execute, NoData, Parse, ParameterDescription, ParseComplete,
+    BindComplete, PgWireBackendMessage, PgWireFrontendMessage, ReadyForQuery, RowDescription, RowDescriptionField,
+};
+
+/// The protocol-version-field value a `CancelRequest` startup packet
+/// carries in place of a real protocol version, per the frontend/backend
+/// protocol.
+const CANCEL_REQUEST_CODE: i32 = 80_877_102;
+
+/// The `ExtendedQueryHandler::Statement` type a `PgWireHandlerFactory`'s
+/// handlers agree on -- the type every [`Connection`] in a given
+/// `process_socket` instantiation stores its prepared statements and
+/// portals as.
+type Statement<H> = <<H as PgWireHandlerFactory>::ExtendedQueryHandler as ExtendedQueryHandler>::Statement;
+
+/// A client info holder paired with the `Sink` its backend messages are
+/// written through, the combination [`crate::api::auth::StartupHandler`]
+/// and friends need. `St` is the prepared-statement representation the
+/// connection's `PortalStore` holds, per [`Statement`].
+struct Connection<St> {
+    info: DefaultClient<St>,
+    sink: Pin<Box<dyn Sink<PgWireBackendMessage, Error = std::io::Error> + Send + Sync>>,
+}
+
+impl<St> ClientInfo for Connection<St> {
+    fn socket_addr(&self) -> SocketAddr {
+        self.info.socket_addr()
+    }
+
+    fn is_secure(&self) -> bool {
+        self.info.is_secure()
+    }
+
+    fn state(&self) -> PgWireConnectionState {
+        self.info.state()
+    }
+
+    fn set_state(&mut self, new_state: PgWireConnectionState) {
+        self.info.set_state(new_state)
+    }
+
+    fn metadata(&self) -> &HashMap<String, String> {
+        self.info.metadata()
+    }
+
+    fn metadata_mut(&mut self) -> &mut HashMap<String, String> {
+        self.info.metadata_mut()
+    }
+}
+
+impl<St> Sink<PgWireBackendMessage> for Connection<St> {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().sink.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: PgWireBackendMessage) -> Result<(), Self::Error> {
+        self.get_mut().sink.as_mut().start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().sink.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().sink.as_mut().poll_close(cx)
+    }
+}
+
+impl<St> ClientPortalStore for Connection<St> {
+    type PortalStore = crate::api::store::MemPortalStore<St>;
+
+    fn portal_store(&self) -> &Self::PortalStore {
+        self.info.portal_store()
+    }
+}
+
+/// Drive a single accepted connection through startup, authentication and
+/// the query loop until the client disconnects.
+///
+/// `tls_acceptor` is reserved for SSL negotiation support and is currently
+/// unused by the handshake below.
+pub async fn process_socket<H>(socket: TcpStream, _tls_acceptor: Option<()>, handler_factory: Arc<H>)
+where
+    H: PgWireHandlerFactory,
+    Statement<H>: Clone,
+{
+    let socket_addr = match socket.peer_addr() {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+    let (mut reader, writer) = socket.into_split();
+
+    let startup = match read_startup_packet(&mut reader).await {
+        Ok(startup) => startup,
+        Err(_) => return,
+    };
+
+    if let StartupPacket::CancelRequest(token) = startup {
+        let registry = handler_factory.cancellation_registry();
+        if registry.verify(token) {
+            // Look the canceller up by `process_id` rather than building a
+            // handler from this (brand new, otherwise unrelated) socket's
+            // own `handler_factory`: that would reach a handler for the
+            // wrong connection entirely, not the session `token` names.
+            if let Some(cancel) = registry.cancel(token.process_id) {
+                let _ = cancel.await;
+            }
+        }
+        // Per the protocol, a `CancelRequest` connection is one-shot: the
+        // server never replies, it just closes the socket either way.
+        return;
+    }
+
+    let mut conn: Connection<Statement<H>> = Connection {
+        info: DefaultClient::new(socket_addr, false),
+        sink: backend_sink(writer),
+    };
+
+    if handler_factory
+        .startup_handler()
+        .on_startup(&mut conn)
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    if conn.send(PgWireBackendMessage::AuthenticationOk(AuthenticationOk)).await.is_err() {
+        return;
+    }
+
+    let cancellation_registry = handler_factory.cancellation_registry();
+    let token = cancellation_registry.register();
+
+    // Attach a canceller that reaches back to *this* connection's own
+    // `CancelHandler`, so a `CancelRequest` replaying `token` later --
+    // necessarily from a different connection -- can be routed here
+    // instead of to whatever handler that other connection happens to be
+    // built with.
+    let cancel_handler = handler_factory.cancel_handler();
+    cancellation_registry.set_canceller(
+        token.process_id,
+        Box::new(move || -> BoxFuture<'static, PgWireResult<()>> {
+            let cancel_handler = cancel_handler.clone();
+            Box::pin(async move {
+                let client_info = DefaultClient::<()>::new(socket_addr, false);
+                cancel_handler.on_cancel(&client_info, token).await
+            })
+        }),
+    );
+
+    if conn
+        .send(PgWireBackendMessage::BackendKeyData(BackendKeyData {
+            process_id: token.process_id,
+            secret_key: token.secret_key,
+        }))
+        .await
+        .is_err()
+    {
+        cancellation_registry.deregister(token);
+        return;
+    }
+
+    conn.set_state(PgWireConnectionState::ReadyForQuery);
+    if send_ready_for_query(&mut conn).await.is_err() {
+        cancellation_registry.deregister(token);
+        return;
+    }
+
+    let simple_query_handler = handler_factory.simple_query_handler();
+    let extended_query_handler = handler_factory.extended_query_handler();
+    let copy_handler = handler_factory.copy_handler();
+
+    loop {
+        let message = match read_frontend_message(&mut reader).await {
+            Ok(Some(message)) => message,
+            Ok(None) | Err(_) => break,
+        };
+
+        match (conn.state(), message) {
+            (_, PgWireFrontendMessage::Terminate) => break,
+
+            (PgWireConnectionState::CopyInProgress(_), PgWireFrontendMessage::CopyData(CopyData(data))) => {
+                if let Err(e) = copy_handler.on_copy_data(&conn, data).await {
+                    if send_error(&mut conn, &e).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            (PgWireConnectionState::CopyInProgress(_), PgWireFrontendMessage::CopyDone(CopyDone)) => {
+                conn.set_state(PgWireConnectionState::ReadyForQuery);
+                match copy_handler.on_copy_done(&conn).await {
+                    Ok(tag) => {
+                        let text = command_complete_text(&tag.name, tag.rows);
+                        if conn
+                            .send(PgWireBackendMessage::CommandComplete(CommandComplete(text)))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        if send_error(&mut conn, &e).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                if send_ready_for_query(&mut conn).await.is_err() {
+                    break;
+                }
+            }
+            (PgWireConnectionState::CopyInProgress(_), PgWireFrontendMessage::CopyFail(CopyFail(message))) => {
+                conn.set_state(PgWireConnectionState::ReadyForQuery);
+                let info = copy_handler.on_copy_fail(&conn, message).await;
+                if send_error(&mut conn, &PgWireError::UserError(Box::new(info))).await.is_err() {
+                    break;
+                }
+                if send_ready_for_query(&mut conn).await.is_err() {
+                    break;
+                }
+            }
+            (PgWireConnectionState::CopyInProgress(_), _) => {
+                let err = PgWireError::InvalidProtocolMessage(
+                    "only CopyData/CopyDone/CopyFail are accepted while a COPY FROM STDIN is in progress"
+                        .to_owned(),
+                );
+                if send_error(&mut conn, &err).await.is_err() {
+                    break;
+                }
+            }
+
+            (_, PgWireFrontendMessage::Query(query)) => {
+                if dispatch_query(&mut conn, &*simple_query_handler, &*copy_handler, &query)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                // `COPY FROM STDIN` leaves the cycle open until a later
+                // `CopyDone`/`CopyFail` -- don't report ready until then.
+                if !matches!(conn.state(), PgWireConnectionState::CopyInProgress(_))
+                    && send_ready_for_query(&mut conn).await.is_err()
+                {
+                    break;
+                }
+            }
+
+            (_, PgWireFrontendMessage::Parse(parse)) => {
+                if dispatch_parse(&mut conn, &*extended_query_handler, parse).await.is_err() {
+                    break;
+                }
+            }
+            (_, PgWireFrontendMessage::Bind(bind)) => {
+                if dispatch_bind(&mut conn, bind).await.is_err() {
+                    break;
+                }
+            }
+            (_, PgWireFrontendMessage::Describe(describe)) => {
+                if dispatch_describe(&mut conn, &*extended_query_handler, describe).await.is_err() {
+                    break;
+                }
+            }
+            (_, PgWireFrontendMessage::Execute(execute)) => {
+                if dispatch_execute(&mut conn, &*extended_query_handler, execute).await.is_err() {
+                    break;
+                }
+            }
+            (_, PgWireFrontendMessage::Sync) => {
+                if send_ready_for_query(&mut conn).await.is_err() {
+                    break;
+                }
+            }
+
+            // A CopyData/CopyDone/CopyFail arriving outside CopyInProgress
+            // is a protocol violation; report it and keep the connection.
+            (_, _) => {
+                let err = PgWireError::InvalidProtocolMessage(
+                    "unexpected COPY message outside of a COPY FROM STDIN".to_owned(),
+                );
+                if send_error(&mut conn, &err).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    cancellation_registry.deregister(token);
+}
+
+/// Recognizes `query` as a simple statement, a `COPY ... FROM STDIN` or a
+/// `COPY ... TO STDOUT`, and drives it to completion against `conn`.
+async fn dispatch_query<St, S, C>(
+    conn: &mut Connection<St>,
+    simple_query_handler: &S,
+    copy_handler: &C,
+    query: &str,
+) -> Result<(), std::io::Error>
+where
+    S: SimpleQueryHandler,
+    C: CopyHandler,
+{
+    let result = if is_copy_from_stdin(query) {
+        match copy_handler.copy_in_response(conn, query).await {
+            Ok(resp) => {
+                conn.set_state(PgWireConnectionState::CopyInProgress(
+                    resp.format == crate::api::results::FieldFormat::Binary,
+                ));
+                conn.send(PgWireBackendMessage::CopyInResponse(CopyInResponse {
+                    overall_format: format_code(resp.format),
+                    column_formats: resp.column_formats.iter().map(|f| i16::from(*f)).collect(),
+                }))
+                .await
+            }
+            Err(e) => send_error(conn, &e).await,
+        }
+    } else if is_copy_to_stdout(query) {
+        match copy_handler.copy_out(conn, query).await {
+            Ok((resp, mut stream)) => {
+                conn.send(PgWireBackendMessage::CopyOutResponse(CopyOutResponse {
+                    overall_format: format_code(resp.format),
+                    column_formats: resp.column_formats.iter().map(|f| i16::from(*f)).collect(),
+                }))
+                .await?;
+                let mut failure = None;
+                while let Some(chunk) = stream.next().await {
+                    match chunk {
+                        Ok(bytes) => conn.send(PgWireBackendMessage::CopyData(CopyData(bytes))).await?,
+                        Err(e) => {
+                            failure = Some(e);
+                            break;
+                        }
+                    }
+                }
+                match failure {
+                    Some(e) => send_error(conn, &e).await,
+                    None => {
+                        conn.send(PgWireBackendMessage::CopyDone(CopyDone)).await?;
+                        // A `CopyData` chunk is not a row -- the stream has no
+                        // notion of row count, so report the bare tag like
+                        // real Postgres does for `COPY ... TO STDOUT`.
+                        conn.send(PgWireBackendMessage::CommandComplete(CommandComplete(
+                            command_complete_text("COPY", None),
+                        )))
+                        .await
+                    }
+                }
+            }
+            Err(e) => send_error(conn, &e).await,
+        }
+    } else {
+        match simple_query_handler.do_query(conn, query).await {
+            Ok(responses) => {
+                let mut result = Ok(());
+                for response in responses {
+                    result = send_response(conn, response).await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                result
+            }
+            Err(e) => send_error(conn, &e).await,
+        }
+    };
+    result
+}
+
+/// Sends a single `Response` as its wire-framed messages, for the simple
+/// query protocol: a `QueryResponse` becomes `RowDescription` + one
+/// `DataRow` per row + `CommandComplete`; an `Execution` tag becomes
+/// `CommandComplete`; a `PassThrough` stream's already-framed messages are
+/// forwarded to the client one at a time without going through
+/// `DataRowEncoder`.
+///
+/// The extended query protocol's `Execute` uses [`send_execute_response`]
+/// instead -- its `RowDescription` (if any) was already sent by a prior
+/// `Describe`, and resending it here would be a protocol violation.
+async fn send_response<St>(conn: &mut Connection<St>, response: Response<'_>) -> Result<(), std::io::Error> {
+    match response {
+        Response::Query(query_response) => {
+            conn.send(PgWireBackendMessage::RowDescription(row_description_from_schema(
+                &query_response.row_schema,
+            )))
+            .await?;
+            send_query_rows(conn, query_response).await
+        }
+        Response::Execution(tag) => send_execution_tag(conn, &tag).await,
+        Response::PassThrough(messages) => send_pass_through(conn, messages).await,
+    }
+}
+
+/// Sends a `Response` as the extended query protocol's `Execute` answer:
+/// same as [`send_response`], but a `Response::Query`'s `RowDescription` is
+/// skipped since `Describe` already sent it.
+async fn send_execute_response<St>(conn: &mut Connection<St>, response: Response<'_>) -> Result<(), std::io::Error> {
+    match response {
+        Response::Query(query_response) => send_query_rows(conn, query_response).await,
+        Response::Execution(tag) => send_execution_tag(conn, &tag).await,
+        Response::PassThrough(messages) => send_pass_through(conn, messages).await,
+    }
+}
+
+/// Streams a `QueryResponse`'s rows as `DataRow` messages, then reports
+/// `CommandComplete` with the row count.
+async fn send_query_rows<St>(conn: &mut Connection<St>, query_response: QueryResponse<'_>) -> Result<(), std::io::Error> {
+    let mut data_rows = query_response.data_rows;
+    let mut count = 0usize;
+    while let Some(row) = data_rows.next().await {
+        match row {
+            Ok(row) => {
+                conn.send(PgWireBackendMessage::DataRow(DataRow { fields: row.fields }))
+                    .await?;
+                count += 1;
+            }
+            Err(e) => return send_error(conn, &e).await,
+        }
+    }
+
+    conn.send(PgWireBackendMessage::CommandComplete(CommandComplete(
+        command_complete_text("SELECT", Some(count)),
+    )))
+    .await
+}
+
+async fn send_execution_tag<St>(conn: &mut Connection<St>, tag: &Tag) -> Result<(), std::io::Error> {
+    conn.send(PgWireBackendMessage::CommandComplete(CommandComplete(
+        command_complete_text(&tag.name, tag.rows),
+    )))
+    .await
+}
+
+async fn send_pass_through<St>(
+    conn: &mut Connection<St>,
+    mut messages: PassThroughStream<'_>,
+) -> Result<(), std::io::Error> {
+    while let Some(message) = messages.next().await {
+        match message {
+            Ok(message) => conn.send(message).await?,
+            Err(e) => return send_error(conn, &e).await,
+        }
+    }
+    Ok(())
+}
+
+/// Handles a `Parse` message: parses `parse.query` via the handler's
+/// `QueryParser` and stores it under `parse.name`, responding with
+/// `ParseComplete`.
+async fn dispatch_parse<St, Q>(conn: &mut Connection<St>, extended_query_handler: &Q, parse: Parse) -> Result<(), std::io::Error>
+where
+    St: Clone,
+    Q: ExtendedQueryHandler<Statement = St>,
+{
+    let parameter_types = parse
+        .parameter_type_oids
+        .iter()
+        .map(|&oid| crate::api::Type::from_oid(oid as u32).unwrap_or(crate::api::Type::UNKNOWN))
+        .collect();
+
+    match extended_query_handler.query_parser().parse_sql(&parse.query).await {
+        Ok(statement) => {
+            conn.portal_store()
+                .put_statement(StoredStatement::new(parse.name, statement, parameter_types));
+            conn.send(PgWireBackendMessage::ParseComplete(ParseComplete)).await
+        }
+        Err(e) => send_error(conn, &e).await,
+    }
+}
+
+/// Handles a `Bind` message: binds `bind.statement_name`'s stored statement
+/// with the given parameters into a portal under `bind.portal_name`,
+/// responding with `BindComplete`.
+async fn dispatch_bind<St>(conn: &mut Connection<St>, bind: Bind) -> Result<(), std::io::Error>
+where
+    St: Clone,
+{
+    let statement = match conn.portal_store().get_statement(&bind.statement_name) {
+        Some(statement) => statement,
+        None => return send_error(conn, &statement_not_found(&bind.statement_name)).await,
+    };
+
+    match Portal::try_new(bind.portal_name.clone(), statement, &bind) {
+        Ok(portal) => {
+            conn.portal_store().put_portal(portal);
+            conn.send(PgWireBackendMessage::BindComplete(BindComplete)).await
+        }
+        Err(e) => send_error(conn, &e).await,
+    }
+}
+
+/// Handles a `Describe` message for either a statement or a portal,
+/// responding with `ParameterDescription`+`RowDescription`/`NoData` or
+/// just `RowDescription`/`NoData` respectively.
+async fn dispatch_describe<St, Q>(
+    conn: &mut Connection<St>,
+    extended_query_handler: &Q,
+    describe: Describe,
+) -> Result<(), std::io::Error>
+where
+    St: Clone,
+    Q: ExtendedQueryHandler<Statement = St>,
+{
+    match describe {
+        Describe::Statement(name) => {
+            let statement = match conn.portal_store().get_statement(&name) {
+                Some(statement) => statement,
+                None => return send_error(conn, &statement_not_found(&name)).await,
+            };
+            match extended_query_handler.do_describe_statement(conn, &statement).await {
+                Ok(response) => send_describe_statement_response(conn, response).await,
+                Err(e) => send_error(conn, &e).await,
+            }
+        }
+        Describe::Portal(name) => {
+            let portal = match conn.portal_store().get_portal(&name) {
+                Some(portal) => portal,
+                None => return send_error(conn, &portal_not_found(&name)).await,
+            };
+            match extended_query_handler.do_describe_portal(conn, &portal).await {
+                Ok(response) => send_describe_portal_response(conn, response).await,
+                Err(e) => send_error(conn, &e).await,
+            }
+        }
+    }
+}
+
+/// Handles an `Execute` message: runs `execute.name`'s portal and sends its
+/// result the same way [`send_execute_response`] would for any other
+/// `Response`.
+async fn dispatch_execute<St, Q>(
+    conn: &mut Connection<St>,
+    extended_query_handler: &Q,
+    execute: Execute,
+) -> Result<(), std::io::Error>
+where
+    St: Clone,
+    Q: ExtendedQueryHandler<Statement = St>,
+{
+    let portal = match conn.portal_store().get_portal(&execute.name) {
+        Some(portal) => portal,
+        None => return send_error(conn, &portal_not_found(&execute.name)).await,
+    };
+
+    // `0` means "no limit", per the protocol.
+    let max_rows = if execute.max_rows <= 0 {
+        usize::MAX
+    } else {
+        execute.max_rows as usize
+    };
+
+    match extended_query_handler.do_query(conn, &portal, max_rows).await {
+        Ok(response) => send_execute_response(conn, response).await,
+        Err(e) => send_error(conn, &e).await,
+    }
+}
+
+async fn send_describe_statement_response<St>(
+    conn: &mut Connection<St>,
+    response: DescribeStatementResponse,
+) -> Result<(), std::io::Error> {
+    conn.send(PgWireBackendMessage::ParameterDescription(ParameterDescription {
+        parameter_type_oids: response.parameter_types.iter().map(|ty| ty.oid() as i32).collect(),
+    }))
+    .await?;
+
+    if response.fields.is_empty() {
+        conn.send(PgWireBackendMessage::NoData(NoData)).await
+    } else {
+        conn.send(PgWireBackendMessage::RowDescription(row_description_from_schema(&response.fields)))
+            .await
+    }
+}
+
+async fn send_describe_portal_response<St>(
+    conn: &mut Connection<St>,
+    response: DescribePortalResponse,
+) -> Result<(), std::io::Error> {
+    if response.fields.is_empty() {
+        conn.send(PgWireBackendMessage::NoData(NoData)).await
+    } else {
+        conn.send(PgWireBackendMessage::RowDescription(row_description_from_schema(&response.fields)))
+            .await
+    }
+}
+
+fn statement_not_found(name: &str) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        SqlState::from("26000"),
+        format!("prepared statement \"{}\" does not exist", name),
+    )))
+}
+
+fn portal_not_found(name: &str) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        SqlState::from("34000"),
+        format!("portal \"{}\" does not exist", name),
+    )))
+}
+
+/// Recognizes `query` as a `COPY ... FROM STDIN`.
+///
+/// Checking `.contains("COPY")`/`.contains("FROM STDIN")` against the whole,
+/// uppercased statement misfires on a query that merely mentions those
+/// words, e.g. `SELECT 'do a COPY FROM STDIN sometime'` -- so this instead
+/// requires `COPY` as the statement's leading keyword and `FROM STDIN` as
+/// its trailing clause.
+fn is_copy_from_stdin(query: &str) -> bool {
+    copy_kind(query) == Some(CopyKind::From)
+}
+
+/// Recognizes `query` as a `COPY ... TO STDOUT`. See [`is_copy_from_stdin`].
+fn is_copy_to_stdout(query: &str) -> bool {
+    copy_kind(query) == Some(CopyKind::To)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyKind {
+    From,
+    To,
+}
+
+fn copy_kind(query: &str) -> Option<CopyKind> {
+    let trimmed = query.trim_start();
+    if !trimmed.get(0..4)?.eq_ignore_ascii_case("COPY") {
+        return None;
+    }
+    let rest = &trimmed[4..];
+    if !rest.starts_with(|c: char| c.is_whitespace() || c == '(') {
+        return None;
+    }
+    let upper_rest = rest.trim_end_matches(';').trim_end().to_ascii_uppercase();
+    if upper_rest.ends_with("FROM STDIN") {
+        Some(CopyKind::From)
+    } else if upper_rest.ends_with("TO STDOUT") {
+        Some(CopyKind::To)
+    } else {
+        None
+    }
+}
+
+fn command_complete_text(name: &str, rows: Option<usize>) -> String {
+    match (name.is_empty(), rows) {
+        (true, Some(n)) => n.to_string(),
+        (false, Some(n)) => format!("{} {}", name, n),
+        (_, None) => name.to_owned(),
+    }
+}
+
+fn format_code(format: crate::api::results::FieldFormat) -> i8 {
+    match format {
+        crate::api::results::FieldFormat::Text => 0,
+        crate::api::results::FieldFormat::Binary => 1,
+    }
+}
+
+fn row_description_from_schema(schema: &[crate::api::results::FieldInfo]) -> RowDescription {
+    RowDescription {
+        fields: schema
+            .iter()
+            .map(|field| RowDescriptionField {
+                name: field.name.clone(),
+                table_id: field.table_id,
+                column_id: field.column_id,
+                type_oid: field.datatype.oid() as i32,
+                format: i16::from(field.format),
+            })
+            .collect(),
+    }
+}
+
+/// Sends `ReadyForQuery`, telling the client the current command cycle has
+/// ended and it may send its next one. Real clients block indefinitely
+/// without it.
+async fn send_ready_for_query<St>(conn: &mut Connection<St>) -> Result<(), std::io::Error> {
+    conn.send(PgWireBackendMessage::ReadyForQuery(ReadyForQuery)).await
+}
+
+/// Sends `error` as an `ErrorResponse` without tearing down the
+/// connection; only an I/O failure while sending it is propagated.
+async fn send_error<St>(conn: &mut Connection<St>, error: &PgWireError) -> Result<(), std::io::Error> {
+    conn.send(PgWireBackendMessage::ErrorResponse(error_response_for(error)))
+        .await
+}
+
+fn error_response_for(error: &PgWireError) -> ErrorResponse {
+    match error {
+        PgWireError::UserError(info) => (**info).clone().into(),
+        PgWireError::InvalidProtocolMessage(message) => {
+            ErrorResponse::new("ERROR".to_owned(), SqlState::from("08P01").code().to_owned(), message.clone())
+        }
+        PgWireError::ApiError(e) => {
+            ErrorResponse::new("ERROR".to_owned(), SqlState::from("XX000").code().to_owned(), e.to_string())
+        }
+        PgWireError::IoError(e) => {
+            ErrorResponse::new("ERROR".to_owned(), SqlState::ConnectionFailure.code().to_owned(), e.to_string())
+        }
+    }
+}
+
+/// What a startup packet turned out to be: a normal startup (parameters
+/// are not modeled or needed by [`crate::api::auth::noop::NoopStartupHandler`])
+/// or a `CancelRequest`.
+enum StartupPacket {
+    Startup,
+    CancelRequest(CancelToken),
+}
+
+async fn read_startup_packet(reader: &mut OwnedReadHalf) -> std::io::Result<StartupPacket> {
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf).await?;
+    let len = i32::from_be_bytes(len_buf) as usize;
+    let mut payload = vec![0u8; len.saturating_sub(4)];
+    reader.read_exact(&mut payload).await?;
+
+    if payload.len() >= 4 {
+        let code = i32::from_be_bytes(payload[0..4].try_into().unwrap());
+        if code == CANCEL_REQUEST_CODE && payload.len() >= 12 {
+            let process_id = i32::from_be_bytes(payload[4..8].try_into().unwrap());
+            let secret_key = i32::from_be_bytes(payload[8..12].try_into().unwrap());
+            return Ok(StartupPacket::CancelRequest(CancelToken {
+                process_id,
+                secret_key,
+            }));
+        }
+    }
+    Ok(StartupPacket::Startup)
+}
+
+async fn read_frontend_message(reader: &mut OwnedReadHalf) -> std::io::Result<Option<PgWireFrontendMessage>> {
+    let mut tag = [0u8; 1];
+    if let Err(e) = reader.read_exact(&mut tag).await {
+        return match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => Ok(None),
+            _ => Err(e),
+        };
+    }
+
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf).await?;
+    let len = i32::from_be_bytes(len_buf) as usize;
+    let mut payload = vec![0u8; len.saturating_sub(4)];
+    reader.read_exact(&mut payload).await?;
+
+    let message = match tag[0] {
+        b'Q' => PgWireFrontendMessage::Query(cstr(&payload)),
+        b'd' => PgWireFrontendMessage::CopyData(CopyData(payload)),
+        b'c' => PgWireFrontendMessage::CopyDone(CopyDone),
+        b'f' => PgWireFrontendMessage::CopyFail(CopyFail(cstr(&payload))),
+        b'P' => PgWireFrontendMessage::Parse(decode_parse(&payload)?),
+        b'B' => PgWireFrontendMessage::Bind(decode_bind(&payload)?),
+        b'D' => PgWireFrontendMessage::Describe(decode_describe(&payload)?),
+        b'E' => PgWireFrontendMessage::Execute(decode_execute(&payload)?),
+        b'S' => PgWireFrontendMessage::Sync,
+        b'X' => PgWireFrontendMessage::Terminate,
+        other => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unsupported frontend message tag {:?}", other as char),
+            ))
+        }
+    };
+    Ok(Some(message))
+}
+
+fn cstr(buf: &[u8]) -> String {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+/// A cursor over a decoded message's payload, for the extended query
+/// protocol messages (`Parse`/`Bind`/`Describe`/`Execute`) that pack
+/// several fields into one frame instead of just a single C string.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+fn truncated() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "truncated extended query protocol message")
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8]) -> Cursor<'a> {
+        Cursor { buf, pos: 0 }
+    }
+
+    fn read_cstr(&mut self) -> std::io::Result<String> {
+        let end = self.buf[self.pos..].iter().position(|&b| b == 0).ok_or_else(truncated)?;
+        let s = String::from_utf8_lossy(&self.buf[self.pos..self.pos + end]).into_owned();
+        self.pos += end + 1;
+        Ok(s)
+    }
+
+    fn read_i16(&mut self) -> std::io::Result<i16> {
+        let bytes: [u8; 2] = self.take(2)?.try_into().unwrap();
+        Ok(i16::from_be_bytes(bytes))
+    }
+
+    fn read_i32(&mut self) -> std::io::Result<i32> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+        Ok(i32::from_be_bytes(bytes))
+    }
+
+    fn take(&mut self, n: usize) -> std::io::Result<&'a [u8]> {
+        if self.buf.len() - self.pos < n {
+            return Err(truncated());
+        }
+        let slice = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+}
+
+fn decode_parse(payload: &[u8]) -> std::io::Result<Parse> {
+    let mut cursor = Cursor::new(payload);
+    let name = cursor.read_cstr()?;
+    let query = cursor.read_cstr()?;
+    let param_count = cursor.read_i16()? as usize;
+    let mut parameter_type_oids = Vec::with_capacity(param_count);
+    for _ in 0..param_count {
+        parameter_type_oids.push(cursor.read_i32()?);
+    }
+    Ok(Parse {
+        name,
+        query,
+        parameter_type_oids,
+    })
+}
+
+fn decode_bind(payload: &[u8]) -> std::io::Result<Bind> {
+    let mut cursor = Cursor::new(payload);
+    let portal_name = cursor.read_cstr()?;
+    let statement_name = cursor.read_cstr()?;
+
+    let format_code_count = cursor.read_i16()? as usize;
+    let mut parameter_format_codes = Vec::with_capacity(format_code_count);
+    for _ in 0..format_code_count {
+        parameter_format_codes.push(cursor.read_i16()?);
+    }
+
+    let parameter_count = cursor.read_i16()? as usize;
+    let mut parameters = Vec::with_capacity(parameter_count);
+    for _ in 0..parameter_count {
+        let len = cursor.read_i32()?;
+        if len < 0 {
+            parameters.push(None);
+        } else {
+            parameters.push(Some(cursor.take(len as usize)?.to_vec()));
+        }
+    }
+
+    let result_format_code_count = cursor.read_i16()? as usize;
+    let mut result_column_format_codes = Vec::with_capacity(result_format_code_count);
+    for _ in 0..result_format_code_count {
+        result_column_format_codes.push(cursor.read_i16()?);
+    }
+
+    Ok(Bind {
+        portal_name,
+        statement_name,
+        parameter_format_codes,
+        parameters,
+        result_column_format_codes,
+    })
+}
+
+fn decode_describe(payload: &[u8]) -> std::io::Result<Describe> {
+    let mut cursor = Cursor::new(payload);
+    let kind = cursor.take(1)?[0];
+    let name = cursor.read_cstr()?;
+    match kind {
+        b'S' => Ok(Describe::Statement(name)),
+        b'P' => Ok(Describe::Portal(name)),
+        other => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unsupported Describe target {:?}", other as char),
+        )),
+    }
+}
+
+fn decode_execute(payload: &[u8]) -> std::io::Result<Execute> {
+    let mut cursor = Cursor::new(payload);
+    let name = cursor.read_cstr()?;
+    let max_rows = cursor.read_i32()?;
+    Ok(Execute { name, max_rows })
+}
+
+fn backend_sink(
+    writer: OwnedWriteHalf,
+) -> Pin<Box<dyn Sink<PgWireBackendMessage, Error = std::io::Error> + Send + Sync>> {
+    Box::pin(futures::sink::unfold(writer, |mut writer, message: PgWireBackendMessage| async move {
+        writer.write_all(&encode_backend_message(&message)).await?;
+        Ok::<_, std::io::Error>(writer)
+    }))
+}
+
+fn encode_backend_message(message: &PgWireBackendMessage) -> Vec<u8> {
+    let (tag, payload) = match message {
+        PgWireBackendMessage::AuthenticationOk(_) => (b'R', 0i32.to_be_bytes().to_vec()),
+        PgWireBackendMessage::ErrorResponse(e) => (b'E', encode_error_fields(e)),
+        PgWireBackendMessage::NoticeResponse(e) => (b'N', encode_error_fields(e)),
+        PgWireBackendMessage::CopyInResponse(r) => (b'G', encode_copy_header(r.overall_format, &r.column_formats)),
+        PgWireBackendMessage::CopyOutResponse(r) => (b'H', encode_copy_header(r.overall_format, &r.column_formats)),
+        PgWireBackendMessage::CopyData(d) => (b'd', d.0.clone()),
+        PgWireBackendMessage::CopyDone(_) => (b'c', Vec::new()),
+        PgWireBackendMessage::BackendKeyData(k) => {
+            let mut buf = Vec::with_capacity(8);
+            buf.extend_from_slice(&k.process_id.to_be_bytes());
+            buf.extend_from_slice(&k.secret_key.to_be_bytes());
+            (b'K', buf)
+        }
+        PgWireBackendMessage::ParseComplete(_) => (b'1', Vec::new()),
+        PgWireBackendMessage::BindComplete(_) => (b'2', Vec::new()),
+        PgWireBackendMessage::ParameterDescription(pd) => {
+            let mut buf = Vec::with_capacity(2 + 4 * pd.parameter_type_oids.len());
+            buf.extend_from_slice(&(pd.parameter_type_oids.len() as i16).to_be_bytes());
+            for oid in &pd.parameter_type_oids {
+                buf.extend_from_slice(&oid.to_be_bytes());
+            }
+            (b't', buf)
+        }
+        PgWireBackendMessage::NoData(_) => (b'n', Vec::new()),
+        PgWireBackendMessage::RowDescription(rd) => (b'T', encode_row_description(rd)),
+        PgWireBackendMessage::DataRow(row) => (b'D', encode_data_row(row)),
+        PgWireBackendMessage::CommandComplete(cc) => {
+            let mut buf = cc.0.clone().into_bytes();
+            buf.push(0);
+            (b'C', buf)
+        }
+        // `I` (idle, not in a transaction): this crate does not model
+        // transaction blocks, so every `ReadyForQuery` reports idle.
+        PgWireBackendMessage::ReadyForQuery(_) => (b'Z', vec![b'I']),
+    };
+
+    let mut framed = Vec::with_capacity(5 + payload.len());
+    framed.push(tag);
+    framed.extend_from_slice(&((payload.len() + 4) as i32).to_be_bytes());
+    framed.extend_from_slice(&payload);
+    framed
+}
+
+fn encode_error_fields(error: &ErrorResponse) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.push(b'S');
+    buf.extend_from_slice(error.severity.as_bytes());
+    buf.push(0);
+    buf.push(b'C');
+    buf.extend_from_slice(error.code.as_bytes());
+    buf.push(0);
+    buf.push(b'M');
+    buf.extend_from_slice(error.message.as_bytes());
+    buf.push(0);
+    if let Some(detail) = &error.detail {
+        buf.push(b'D');
+        buf.extend_from_slice(detail.as_bytes());
+        buf.push(0);
+    }
+    if let Some(hint) = &error.hint {
+        buf.push(b'H');
+        buf.extend_from_slice(hint.as_bytes());
+        buf.push(0);
+    }
+    if let Some(position) = error.position {
+        buf.push(b'P');
+        buf.extend_from_slice(position.to_string().as_bytes());
+        buf.push(0);
+    }
+    buf.push(0);
+    buf
+}
+
+fn encode_copy_header(format: i8, column_formats: &[i16]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(3 + column_formats.len() * 2);
+    buf.push(format as u8);
+    buf.extend_from_slice(&(column_formats.len() as i16).to_be_bytes());
+    for code in column_formats {
+        buf.extend_from_slice(&code.to_be_bytes());
+    }
+    buf
+}
+
+fn encode_row_description(description: &RowDescription) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(description.fields.len() as i16).to_be_bytes());
+    for field in &description.fields {
+        buf.extend_from_slice(field.name.as_bytes());
+        buf.push(0);
+        buf.extend_from_slice(&field.table_id.to_be_bytes());
+        buf.extend_from_slice(&field.column_id.to_be_bytes());
+        buf.extend_from_slice(&field.type_oid.to_be_bytes());
+        buf.extend_from_slice(&0i16.to_be_bytes()); // typlen: not tracked by FieldInfo
+        buf.extend_from_slice(&(-1i32).to_be_bytes()); // typmod: not tracked by FieldInfo
+        buf.extend_from_slice(&field.format.to_be_bytes());
+    }
+    buf
+}
+
+fn encode_data_row(row: &DataRow) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(row.fields.len() as i16).to_be_bytes());
+    for field in &row.fields {
+        match field {
+            Some(bytes) => {
+                buf.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+                buf.extend_from_slice(bytes);
+            }
+            None => buf.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_kind_recognizes_from_stdin_and_to_stdout() {
+        assert_eq!(copy_kind("COPY t FROM STDIN"), Some(CopyKind::From));
+        assert_eq!(copy_kind("  copy t from stdin;"), Some(CopyKind::From));
+        assert_eq!(copy_kind("COPY (SELECT 1) TO STDOUT"), Some(CopyKind::To));
+        assert_eq!(copy_kind("copy t to stdout ;"), Some(CopyKind::To));
+    }
+
+    #[test]
+    fn copy_kind_ignores_queries_that_merely_mention_copy() {
+        assert_eq!(copy_kind("SELECT 'do a COPY FROM STDIN sometime'"), None);
+        assert_eq!(copy_kind("SELECT * FROM copies"), None);
+        assert_eq!(copy_kind("COPY t FROM '/tmp/file'"), None);
+    }
+}